@@ -0,0 +1,151 @@
+use error::SequencerError;
+use pcm::{Frame, PCMParameters, Sample, PCM};
+use Result;
+
+/// Audio as plain interleaved floats, for using rendered audio without going through the pcm crate
+#[derive(Clone, Debug, Default)]
+pub struct AudioBuffer {
+    /// Sample rate in Hertz
+    pub sample_rate: u32,
+    /// Amount of channels
+    pub nb_channels: u16,
+    /// All samples, one frame after the other
+    pub samples: Vec<f32>,
+}
+
+/// Copies the samples of a PCM containing Float samples into an AudioBuffer
+pub(crate) fn pcm_to_buffer(pcm: &PCM) -> Result<AudioBuffer> {
+    Ok(AudioBuffer {
+        sample_rate: pcm.parameters.sample_rate,
+        nb_channels: pcm.parameters.nb_channels,
+        samples: pcm.interleaved_f32()?,
+    })
+}
+
+/// Converts an AudioBuffer to a PCM with Float samples
+pub(crate) fn buffer_to_pcm(buffer: &AudioBuffer) -> PCM {
+    PCM {
+        parameters: PCMParameters {
+            sample_rate: buffer.sample_rate,
+            nb_channels: buffer.nb_channels,
+            sample_type: Sample::Float(0f32),
+        },
+        loop_info: None,
+        frames: buffer
+            .frames()
+            .map(|f| Frame {
+                samples: f.iter().map(|s| Sample::Float(*s)).collect(),
+            })
+            .collect(),
+    }
+}
+
+impl AudioBuffer {
+    /// Copies the samples of a PCM containing Float samples. Needs the `pcm` feature.
+    #[cfg(feature = "pcm")]
+    pub fn from_pcm(pcm: &PCM) -> Result<AudioBuffer> {
+        pcm_to_buffer(pcm)
+    }
+    /// Converts to a PCM with Float samples. Needs the `pcm` feature.
+    #[cfg(feature = "pcm")]
+    pub fn to_pcm(&self) -> PCM {
+        buffer_to_pcm(self)
+    }
+    /// Amount of frames, one sample per channel each
+    pub fn nb_frames(&self) -> usize {
+        match self.nb_channels {
+            0 => 0,
+            n => self.samples.len() / n as usize,
+        }
+    }
+    /// Goes through the samples frame by frame
+    pub fn frames(&self) -> ::std::slice::Chunks<'_, f32> {
+        self.samples.chunks(usize::from(self.nb_channels.max(1)))
+    }
+    /// Gives the samples of one channel
+    pub fn channel(&self, channel: usize) -> Result<Vec<f32>> {
+        if channel >= self.nb_channels as usize {
+            return Err(SequencerError::NoChannel(channel));
+        }
+        Ok(self
+            .samples
+            .chunks_exact(self.nb_channels as usize)
+            .map(|f| f[channel])
+            .collect())
+    }
+    /// Length in seconds
+    pub fn duration(&self) -> f64 {
+        self.nb_frames() as f64 / f64::from(self.sample_rate)
+    }
+}
+
+/// Gives access to the samples of a PCM as plain floats, without unwrapping Sample enums.
+/// Only PCMs containing Float samples, like the ones given by the sequencer, are supported.
+pub trait SampleAccess {
+    /// Gives the samples of one channel
+    fn channel_f32(&self, channel: usize) -> Result<Vec<f32>>;
+    /// Gives the samples of each channel separately, one Vec per channel
+    fn deinterleaved_f32(&self) -> Result<Vec<Vec<f32>>>;
+    /// Same as deinterleaved_f32 in double precision
+    fn deinterleaved_f64(&self) -> Result<Vec<Vec<f64>>>;
+    /// Gives all samples one frame after the other, use chunks() with the amount of channels to go frame by frame
+    fn interleaved_f32(&self) -> Result<Vec<f32>>;
+    /// Same as interleaved_f32 in double precision
+    fn interleaved_f64(&self) -> Result<Vec<f64>>;
+}
+
+/// Converts a Sample to a float
+pub fn sample_to_f32(sample: &Sample) -> Result<f32> {
+    match sample {
+        Sample::Float(s) => Ok(*s),
+        _ => Err(SequencerError::UnsupportedSampleType),
+    }
+}
+
+impl SampleAccess for PCM {
+    fn channel_f32(&self, channel: usize) -> Result<Vec<f32>> {
+        let mut out = Vec::with_capacity(self.frames.len());
+        for frame in &self.frames {
+            match frame.samples.get(channel) {
+                Some(s) => out.push(sample_to_f32(s)?),
+                None => return Err(SequencerError::NoChannel(channel)),
+            }
+        }
+        Ok(out)
+    }
+    fn deinterleaved_f32(&self) -> Result<Vec<Vec<f32>>> {
+        let nb_channels = self.parameters.nb_channels as usize;
+        let mut out = vec![Vec::with_capacity(self.frames.len()); nb_channels];
+        for frame in &self.frames {
+            for (channel, samples) in out.iter_mut().enumerate() {
+                match frame.samples.get(channel) {
+                    Some(s) => samples.push(sample_to_f32(s)?),
+                    None => return Err(SequencerError::NoChannel(channel)),
+                }
+            }
+        }
+        Ok(out)
+    }
+    fn deinterleaved_f64(&self) -> Result<Vec<Vec<f64>>> {
+        Ok(self.deinterleaved_f32()?
+            .into_iter()
+            .map(|c| c.into_iter().map(f64::from).collect())
+            .collect())
+    }
+    fn interleaved_f32(&self) -> Result<Vec<f32>> {
+        let nb_channels = self.parameters.nb_channels as usize;
+        let mut out = Vec::with_capacity(self.frames.len() * nb_channels);
+        for frame in &self.frames {
+            for channel in 0..nb_channels {
+                match frame.samples.get(channel) {
+                    Some(s) => out.push(sample_to_f32(s)?),
+                    None => return Err(SequencerError::NoChannel(channel)),
+                }
+            }
+        }
+        Ok(out)
+    }
+    fn interleaved_f64(&self) -> Result<Vec<f64>> {
+        Ok(self.interleaved_f32()?.into_iter().map(f64::from).collect())
+    }
+}
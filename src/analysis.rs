@@ -0,0 +1,302 @@
+use adapters::SampleAccess;
+use error::SequencerError;
+use filters::Biquad;
+use pcm::{Sample, PCM};
+use std::f64::consts::PI;
+use Result;
+
+/// Length of the loudness measurement blocks, in seconds
+const LOUDNESS_BLOCK: f64 = 0.4f64;
+/// Time between the start of two loudness measurement blocks, in seconds
+const LOUDNESS_STEP: f64 = 0.1f64;
+/// Blocks quieter than this are ignored, in LUFS
+const ABSOLUTE_GATE: f64 = -70f64;
+/// Blocks quieter than the ungated loudness minus this are ignored, in LU
+const RELATIVE_GATE: f64 = 10f64;
+/// Length of the FFT blocks used for the spectral centroid, has to be a power of 2
+const SPECTRUM_BLOCK: usize = 2048;
+
+/// Gives the K-weighting filters of ITU-R BS.1770 for a sample rate
+fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+    (
+        Biquad::high_shelf(1681.974450955533, 3.99984385397, 0.7071752369554193, sample_rate),
+        Biquad::high_pass(38.13547087613982, 0.5003270373253953, sample_rate),
+    )
+}
+
+/// Converts a mean square to a loudness in LUFS
+fn mean_square_to_loudness(mean_square: f64) -> f64 {
+    -0.691f64 + 10f64 * mean_square.log10()
+}
+
+/// Measures the integrated loudness of a PCM in LUFS following ITU-R BS.1770, with every channel weighted equally.
+/// Gives negative infinity for silence.
+pub fn integrated_loudness(pcm: &PCM) -> Result<f64> {
+    let sample_rate = f64::from(pcm.parameters.sample_rate);
+    let channels = pcm.deinterleaved_f32()?;
+    let weighted: Vec<Vec<f64>> = channels
+        .iter()
+        .map(|c| {
+            let (mut shelf, mut high_pass) = k_weighting(sample_rate);
+            c.iter()
+                .map(|s| high_pass.process(shelf.process(f64::from(*s))))
+                .collect()
+        })
+        .collect();
+    let nb_frames = pcm.frames.len();
+    let block_size = ((LOUDNESS_BLOCK * sample_rate) as usize).min(nb_frames).max(1);
+    let step = ((LOUDNESS_STEP * sample_rate) as usize).max(1);
+    let mut blocks = Vec::new();
+    let mut start = 0usize;
+    while start + block_size <= nb_frames {
+        let mut sum = 0f64;
+        for channel in &weighted {
+            sum += channel[start..start + block_size]
+                .iter()
+                .map(|s| s * s)
+                .sum::<f64>() / block_size as f64;
+        }
+        blocks.push(sum);
+        start += step;
+    }
+    let above_absolute: Vec<f64> = blocks
+        .into_iter()
+        .filter(|b| mean_square_to_loudness(*b) > ABSOLUTE_GATE)
+        .collect();
+    if above_absolute.is_empty() {
+        return Ok(f64::NEG_INFINITY);
+    }
+    let relative_gate = mean_square_to_loudness(
+        above_absolute.iter().sum::<f64>() / above_absolute.len() as f64,
+    ) - RELATIVE_GATE;
+    let gated: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|b| mean_square_to_loudness(*b) > relative_gate)
+        .collect();
+    Ok(mean_square_to_loudness(
+        gated.iter().sum::<f64>() / gated.len() as f64,
+    ))
+}
+
+/// Gives the highest absolute sample value of a PCM
+pub fn peak(pcm: &PCM) -> Result<f32> {
+    Ok(pcm.interleaved_f32()?
+        .into_iter()
+        .fold(0f32, |peak, s| peak.max(s.abs())))
+}
+
+/// Gives the root mean square of all samples of a PCM
+pub fn rms(pcm: &PCM) -> Result<f64> {
+    let samples = pcm.interleaved_f64()?;
+    if samples.is_empty() {
+        return Ok(0f64);
+    }
+    Ok((samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt())
+}
+
+/// Gives the ratio between the peak and the root mean square of a PCM in dB, how much higher transients go than the average level.
+/// Gives 0 for silence.
+pub fn crest_factor(pcm: &PCM) -> Result<f64> {
+    let rms = rms(pcm)?;
+    if rms == 0f64 {
+        return Ok(0f64);
+    }
+    Ok(20f64 * (f64::from(peak(pcm)?) / rms).log10())
+}
+
+/// Gives the spectral centroid of a PCM in Hertz, the center of mass of its spectrum, higher for brighter sounds.
+/// Channels are mixed together, and the centroids of blocks are averaged weighted by their energy. Gives 0 for silence.
+pub fn spectral_centroid(pcm: &PCM) -> Result<f64> {
+    let nb_channels = (pcm.parameters.nb_channels as usize).max(1);
+    let mono: Vec<f64> = pcm.interleaved_f64()?
+        .chunks(nb_channels)
+        .map(|frame| frame.iter().sum::<f64>() / nb_channels as f64)
+        .collect();
+    let bin_width = f64::from(pcm.parameters.sample_rate) / SPECTRUM_BLOCK as f64;
+    let (mut weighted_sum, mut total) = (0f64, 0f64);
+    for block in mono.chunks(SPECTRUM_BLOCK) {
+        let mut re: Vec<f64> = (0..SPECTRUM_BLOCK)
+            .map(|i| {
+                let window = 0.5f64
+                    - 0.5f64 * (2f64 * PI * i as f64 / (SPECTRUM_BLOCK - 1) as f64).cos();
+                block.get(i).cloned().unwrap_or(0f64) * window
+            })
+            .collect();
+        let mut im = vec![0f64; SPECTRUM_BLOCK];
+        fft(&mut re, &mut im);
+        for bin in 1..SPECTRUM_BLOCK / 2 {
+            let magnitude = (re[bin] * re[bin] + im[bin] * im[bin]).sqrt();
+            weighted_sum += bin as f64 * bin_width * magnitude;
+            total += magnitude;
+        }
+    }
+    if total == 0f64 {
+        return Ok(0f64);
+    }
+    Ok(weighted_sum / total)
+}
+
+/// Radix-2 Fast Fourier Transform done in place, the length has to be a power of 2
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0usize;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut length = 2usize;
+    while length <= n {
+        let angle = -2f64 * PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (w_re, w_im) = ((angle * k as f64).cos(), (angle * k as f64).sin());
+                let (a, b) = (start + k, start + k + length / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+/// Multiplies every sample of a PCM by a gain
+pub fn apply_gain(pcm: &mut PCM, gain: f32) {
+    for frame in &mut pcm.frames {
+        for sample in &mut frame.samples {
+            if let Sample::Float(s) = *sample {
+                *sample = Sample::Float(s * gain);
+            }
+        }
+    }
+}
+
+/// Gives a copy of a PCM with its integrated loudness brought to a target in LUFS. Silence is left as is.
+/// The gain is lowered if needed so that the peak does not go above 1, the result is then quieter than the target.
+pub fn normalize_loudness(pcm: &PCM, target: f64) -> Result<PCM> {
+    let loudness = integrated_loudness(pcm)?;
+    let mut normalized = pcm.clone();
+    if loudness.is_finite() {
+        let mut gain = 10f64.powf((target - loudness) / 20f64);
+        let peak = f64::from(peak(pcm)?);
+        if peak * gain > 1f64 {
+            gain = 1f64 / peak;
+        }
+        apply_gain(&mut normalized, gain as f32);
+    }
+    Ok(normalized)
+}
+
+/// A time region where the mix will likely clip
+#[derive(Clone, Debug)]
+pub struct HeadroomWarning {
+    /// Start of the region in seconds
+    pub start: f64,
+    /// End of the region in seconds
+    pub end: f64,
+    /// Highest sum of the amplitudes of all notes playing in the region, clipping starts above 1
+    pub worst_case_amplitude: f64,
+}
+
+/// Finds the regions where the sum of the worst case amplitudes of notes playing at the same time goes above 1.
+/// # Arguments
+/// * notes: Start, end and worst case amplitude of each note
+pub fn find_clipping_regions(notes: &[(f64, f64, f64)]) -> Vec<HeadroomWarning> {
+    let mut events: Vec<(f64, f64)> = Vec::with_capacity(notes.len() * 2);
+    for &(start, end, amplitude) in notes {
+        events.push((start, amplitude));
+        events.push((end, -amplitude));
+    }
+    // At the same time, notes stopping are processed before notes starting
+    events.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    let mut warnings: Vec<HeadroomWarning> = Vec::new();
+    let mut sum = 0f64;
+    let mut current: Option<HeadroomWarning> = None;
+    for (time, amplitude) in events {
+        sum += amplitude;
+        let clipping = sum > 1f64 + 1e-9;
+        current = match (current, clipping) {
+            (None, true) => Some(HeadroomWarning {
+                start: time,
+                end: time,
+                worst_case_amplitude: sum,
+            }),
+            (Some(mut w), true) => {
+                w.worst_case_amplitude = w.worst_case_amplitude.max(sum);
+                Some(w)
+            }
+            (Some(mut w), false) => {
+                w.end = time;
+                match warnings.last_mut() {
+                    Some(ref mut last) if last.end >= w.start => {
+                        last.end = w.end;
+                        last.worst_case_amplitude =
+                            last.worst_case_amplitude.max(w.worst_case_amplitude);
+                    }
+                    _ => warnings.push(w),
+                }
+                None
+            }
+            (None, false) => None,
+        };
+    }
+    warnings
+}
+
+/// A time region where an Instrument does not make any sound
+#[derive(Clone, Debug)]
+pub struct InactiveRegion {
+    /// Start of the region in seconds
+    pub start: f64,
+    /// End of the region in seconds
+    pub end: f64,
+}
+
+/// Finds the regions where nothing is active for at least a minimum length.
+/// # Arguments
+/// * active: Start and end of each active part, in any order and possibly overlapping
+/// * end: End of the song, the last region goes up to here
+/// * min_length: Shortest region to report, in seconds. Has to be strictly positive.
+pub fn find_inactive_regions(
+    active: &[(f64, f64)],
+    end: f64,
+    min_length: f64,
+) -> Result<Vec<InactiveRegion>> {
+    if min_length.is_nan() | (min_length <= 0f64) {
+        return Err(SequencerError::InvalidParameter(format!(
+            "minimum length {} of inactive regions should be strictly positive",
+            min_length
+        )));
+    }
+    let mut active = active.to_vec();
+    active.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut regions = Vec::new();
+    let mut inactive_since = 0f64;
+    for (start, stop) in active {
+        if start - inactive_since >= min_length {
+            regions.push(InactiveRegion {
+                start: inactive_since,
+                end: start,
+            });
+        }
+        inactive_since = inactive_since.max(stop);
+    }
+    if end - inactive_since >= min_length {
+        regions.push(InactiveRegion {
+            start: inactive_since,
+            end,
+        });
+    }
+    Ok(regions)
+}
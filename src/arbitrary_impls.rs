@@ -0,0 +1,84 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+use std::collections::HashMap;
+use {FrequencyId, FrequencyLookupTable, InstrumentId, LoopInfo, Note, Sequence};
+
+/// Latest time at which a generated Note or LoopInfo can start, in seconds
+const MAX_START: f64 = 600f64;
+/// Longest generated Note, in seconds
+const MAX_NOTE_DURATION: f64 = 30f64;
+/// Shortest generated Note or loop, in seconds
+const MIN_DURATION: f64 = 0.001f64;
+/// Highest Frequency ID used by generated Notes, every ID up to this one is present in a generated FrequencyLookupTable
+const MAX_FREQUENCY_ID: usize = 127;
+/// Highest Instrument ID used by generated Notes
+const MAX_INSTRUMENT_ID: usize = 15;
+/// Largest detune of generated Notes either way, in cents
+const MAX_DETUNE: f64 = 50f64;
+/// Maximum amount of Notes in a generated Sequence
+const MAX_NOTES: usize = 256;
+/// Maximum amount of loops in a generated Sequence
+const MAX_LOOPS: usize = 4;
+
+/// Takes a float in [low, high] from the unstructured data
+fn float_in_range(u: &mut Unstructured, low: f64, high: f64) -> Result<f64> {
+    let step = u.int_in_range(0..=u32::MAX)?;
+    Ok(low + (high - low) * (f64::from(step) / f64::from(u32::MAX)))
+}
+
+impl<'a> Arbitrary<'a> for Note {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let start_at = float_in_range(u, 0f64, MAX_START)?;
+        let duration = float_in_range(u, MIN_DURATION, MAX_NOTE_DURATION)?;
+        Ok(Note {
+            start_at,
+            end_at: start_at + duration,
+            duration,
+            frequency_id: FrequencyId(u.int_in_range(0..=MAX_FREQUENCY_ID)?),
+            on_velocity: float_in_range(u, 0f64, 1f64)?,
+            off_velocity: float_in_range(u, 0f64, 1f64)?,
+            instrument_id: InstrumentId(u.int_in_range(0..=MAX_INSTRUMENT_ID)?),
+            detune: float_in_range(u, -MAX_DETUNE, MAX_DETUNE)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for LoopInfo {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let loop_start = float_in_range(u, 0f64, MAX_START)?;
+        Ok(LoopInfo {
+            loop_start,
+            loop_end: loop_start + float_in_range(u, MIN_DURATION, MAX_START)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Sequence {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let nb_notes = u.int_in_range(0..=MAX_NOTES)?;
+        let mut notes = Vec::with_capacity(nb_notes);
+        for _ in 0..nb_notes {
+            notes.push(Note::arbitrary(u)?);
+        }
+        let loop_info = if u.arbitrary()? {
+            let nb_loops = u.int_in_range(1..=MAX_LOOPS)?;
+            let mut loops = Vec::with_capacity(nb_loops);
+            for _ in 0..nb_loops {
+                loops.push(LoopInfo::arbitrary(u)?);
+            }
+            Some(loops)
+        } else {
+            None
+        };
+        Ok(Sequence { notes, loop_info })
+    }
+}
+
+impl<'a> Arbitrary<'a> for FrequencyLookupTable {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut lut = HashMap::new();
+        for id in 0..=MAX_FREQUENCY_ID {
+            lut.insert(FrequencyId(id), float_in_range(u, 20f64, 20_000f64)?);
+        }
+        Ok(FrequencyLookupTable { lut })
+    }
+}
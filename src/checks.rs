@@ -0,0 +1,50 @@
+#[cfg(feature = "strict-checks")]
+use error::SequencerError;
+use Result;
+
+/// Checks that a mixed sample is within [-1, 1] before being output
+#[cfg(feature = "strict-checks")]
+pub fn check_amplitude(amplitude: f32) -> Result<()> {
+    if !(-1f32..=1f32).contains(&amplitude) {
+        return Err(SequencerError::AmplitudeOutOfRange(f64::from(amplitude)));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "strict-checks"))]
+#[inline(always)]
+pub fn check_amplitude(_amplitude: f32) -> Result<()> {
+    Ok(())
+}
+
+/// Checks that an amplitude given by an Envelope is within [0, 1]
+#[cfg(feature = "strict-checks")]
+pub fn check_envelope_output(amplitude: f64) -> Result<()> {
+    if !(0f64..=1f64).contains(&amplitude) {
+        return Err(SequencerError::EnvelopeOutOfRange(amplitude));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "strict-checks"))]
+#[inline(always)]
+pub fn check_envelope_output(_amplitude: f64) -> Result<()> {
+    Ok(())
+}
+
+/// Checks that frame positions only ever go forward
+#[cfg(feature = "strict-checks")]
+pub fn check_frame_position(previous: Option<usize>, current: usize) -> Result<()> {
+    if let Some(p) = previous {
+        if current < p {
+            return Err(SequencerError::NonMonotonicFramePosition(current));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "strict-checks"))]
+#[inline(always)]
+pub fn check_frame_position(_previous: Option<usize>, _current: usize) -> Result<()> {
+    Ok(())
+}
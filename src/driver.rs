@@ -0,0 +1,275 @@
+use error::SequencerError;
+use pitch;
+use std::rc::Rc;
+use {Envelope, FrequencyLookupTable, InstrumentTable, Note, NoteId, Result, Sequence};
+
+/// Simulates a sound driver with a fixed amount of hardware voices, to hear how a Sequence would behave under its constraints.
+/// Notes start and stop on driver ticks and take a free voice or steal one.
+/// Each voice has a volume register and a pitch register, written once per tick:
+/// * The volume register holds the velocity of the note and the level of the Envelope of its Instrument,
+///   read at the start of each tick and rounded to the steps of the register. See RegisterEnvelope.
+/// * The pitch register holds the frequency of the note, rounded to what a period register can give if a pitch clock is set.
+///   Pitch changes inside the Keys of Instruments, like glides, are not seen by the driver.
+///
+/// Notes shorter than a tick are dropped without taking a voice.
+#[derive(Clone)]
+pub struct HardwareDriver {
+    /// Amount of voices that can play at once
+    pub nb_voices: usize,
+    /// Rate at which the driver updates its voice registers, in Hertz. 60 for a driver running on the vertical blank.
+    /// Has to be a positive number.
+    pub tick_rate: f64,
+    /// Amount of steps of the volume register of each voice. If 0, volumes are not rounded.
+    pub volume_steps: u32,
+    /// Clock of the period register giving the pitch of each voice, in Hertz. Frequencies are rounded to the clock divided by a whole number,
+    /// like the period registers of the NES and the Game Boy. If not set, frequencies are not rounded.
+    pub pitch_clock: Option<f64>,
+    /// What happens when a note starts and every voice is busy
+    pub stealing: VoiceStealing,
+}
+
+/// Defines which voice a HardwareDriver takes when a note starts and every voice is busy
+#[derive(Clone, Copy, PartialEq)]
+pub enum VoiceStealing {
+    /// The new note is not played
+    Never,
+    /// The voice that started playing first is cut
+    Oldest,
+    /// The voice playing the Instrument with the lowest priority is cut, if that priority is not above the one of the new note.
+    /// The oldest voice is cut among voices with the same priority.
+    LowestPriority,
+}
+
+/// What the driver did with a note of the original Sequence
+#[derive(Clone)]
+pub struct VoiceAllocation {
+    /// Index of the note in the original Sequence, sorted by time
+    pub note_id: NoteId,
+    /// Voice that played the note, None if it was not played
+    pub voice: Option<usize>,
+    /// Time at which the note got cut by another note stealing its voice
+    pub cut_at: Option<f64>,
+}
+
+/// Result of a driver simulation
+pub struct DriverOutput {
+    /// Sequence as played by the driver
+    pub sequence: Sequence,
+    /// What happened to each note of the original Sequence
+    pub allocations: Vec<VoiceAllocation>,
+}
+
+/// A note currently playing on a voice
+struct ActiveVoice {
+    /// Index of the note in the original Sequence
+    note_id: usize,
+    /// Tick at which the note started
+    start_tick: u64,
+    /// Tick at which the note ends
+    end_tick: u64,
+    /// Priority of the Instrument of the note
+    priority: u8,
+}
+
+/// Envelope of an Instrument as written in the volume register of a voice by a HardwareDriver.
+/// The level is read at the start of the tick a time falls in and rounded to the steps of the register, so it changes in steps once per tick.
+/// Times are counted from the start of the note, which is on a tick.
+pub struct RegisterEnvelope {
+    /// Envelope of the Instrument
+    pub envelope: Rc<Box<dyn Envelope>>,
+    /// Rate at which the register is written, in Hertz
+    pub tick_rate: f64,
+    /// Amount of steps of the register. If 0, levels are not rounded.
+    pub volume_steps: u32,
+}
+
+impl RegisterEnvelope {
+    /// Gives the start of the tick a time falls in
+    fn tick_start(&self, time: f64) -> f64 {
+        (time * self.tick_rate).floor() / self.tick_rate
+    }
+}
+
+impl Envelope for RegisterEnvelope {
+    fn before_during_sustain(&self, time: &f64) -> f64 {
+        quantize(
+            self.envelope.before_during_sustain(&self.tick_start(*time)),
+            self.volume_steps,
+        )
+    }
+    fn after_sustain(&self, time: &f64) -> f64 {
+        quantize(
+            self.envelope.after_sustain(&self.tick_start(*time)),
+            self.volume_steps,
+        )
+    }
+    fn release_from(&self, time: &f64, level: f64) -> f64 {
+        quantize(
+            self.envelope.release_from(&self.tick_start(*time), level),
+            self.volume_steps,
+        )
+    }
+    fn release_duration(&self) -> f64 {
+        self.envelope.release_duration()
+    }
+}
+
+/// Rounds a level to the steps of a register, levels are not rounded if there are 0 steps
+fn quantize(level: f64, steps: u32) -> f64 {
+    if steps == 0 {
+        level
+    } else {
+        let steps = f64::from(steps);
+        (level * steps).round() / steps
+    }
+}
+
+/// Gives an error unless a value is finite and strictly positive
+fn check_positive(value: f64, name: &str) -> Result<()> {
+    if !value.is_finite() | (value <= 0f64) {
+        return Err(SequencerError::InvalidParameter(format!(
+            "{} {} should be a positive number",
+            name, value
+        )));
+    }
+    Ok(())
+}
+
+impl HardwareDriver {
+    /// Rounds a time to the closest driver tick
+    fn to_tick(&self, time: f64) -> u64 {
+        (time * self.tick_rate).round() as u64
+    }
+    /// Converts a tick to a time in seconds
+    fn to_time(&self, tick: u64) -> f64 {
+        tick as f64 / self.tick_rate
+    }
+    /// Gives the detune making a note play the frequency the pitch register gives for it
+    fn quantize_pitch(&self, note: &Note, frequency_lut: &FrequencyLookupTable) -> Result<f64> {
+        let clock = match self.pitch_clock {
+            Some(c) => c,
+            None => return Ok(note.detune),
+        };
+        let frequency = pitch::shift_by_cents(*frequency_lut.get(&note.frequency_id)?, note.detune);
+        let period = (clock / frequency).round().max(1f64);
+        Ok(note.detune + pitch::cents_between(frequency, clock / period))
+    }
+    /// Wraps the Envelope of an Instrument so that it goes through the volume register of the voices
+    pub fn register_envelope(&self, envelope: Rc<Box<dyn Envelope>>) -> RegisterEnvelope {
+        RegisterEnvelope {
+            envelope,
+            tick_rate: self.tick_rate,
+            volume_steps: self.volume_steps,
+        }
+    }
+    /// Plays a Sequence through the driver, giving the Sequence as heard with the driver constraints.
+    /// Envelopes are not changed, MusicSequencer::render_with_driver makes them go through the volume register with RegisterEnvelope.
+    /// Gives an error if the tick rate or the pitch clock is not a positive number.
+    pub fn simulate(
+        &self,
+        sequence: &Sequence,
+        instruments: &mut InstrumentTable,
+        frequency_lut: &FrequencyLookupTable,
+    ) -> Result<DriverOutput> {
+        check_positive(self.tick_rate, "tick rate")?;
+        if let Some(c) = self.pitch_clock {
+            check_positive(c, "pitch clock")?;
+        }
+        let mut sorted = sequence.clone();
+        sorted.sort_by_time();
+        let mut voices: Vec<Option<ActiveVoice>> = (0..self.nb_voices).map(|_| None).collect();
+        let mut played: Vec<Option<Note>> = Vec::with_capacity(sorted.notes.len());
+        let mut allocations = Vec::with_capacity(sorted.notes.len());
+        for (note_id, note) in sorted.notes.iter().enumerate() {
+            let start_tick = self.to_tick(note.start_at);
+            let end_tick = self.to_tick(note.end_at);
+            let priority = instruments.get(&note.instrument_id)?.priority;
+            for voice in &mut voices {
+                let ended = match voice {
+                    Some(v) => v.end_tick <= start_tick,
+                    None => false,
+                };
+                if ended {
+                    *voice = None;
+                }
+            }
+            if end_tick <= start_tick {
+                allocations.push(VoiceAllocation {
+                    note_id: NoteId(note_id),
+                    voice: None,
+                    cut_at: None,
+                });
+                played.push(None);
+                continue;
+            }
+            let free_voice = voices.iter().position(|v| v.is_none());
+            let voice_id = match free_voice {
+                Some(id) => Some(id),
+                None => self.pick_stolen_voice(&voices, priority),
+            };
+            allocations.push(VoiceAllocation {
+                note_id: NoteId(note_id),
+                voice: voice_id,
+                cut_at: None,
+            });
+            let voice_id = match voice_id {
+                Some(id) => id,
+                None => {
+                    played.push(None);
+                    continue;
+                }
+            };
+            if let Some(ref stolen) = voices[voice_id] {
+                let cut_at = self.to_time(start_tick);
+                allocations[stolen.note_id].cut_at = Some(cut_at);
+                let keep = match played[stolen.note_id] {
+                    Some(ref mut n) => {
+                        n.end_at = cut_at;
+                        n.duration = cut_at - n.start_at;
+                        n.duration > 0f64
+                    }
+                    None => false,
+                };
+                if !keep {
+                    played[stolen.note_id] = None;
+                }
+            }
+            voices[voice_id] = Some(ActiveVoice {
+                note_id,
+                start_tick,
+                end_tick,
+                priority,
+            });
+            played.push(Some(Note {
+                start_at: self.to_time(start_tick),
+                end_at: self.to_time(end_tick),
+                duration: self.to_time(end_tick) - self.to_time(start_tick),
+                on_velocity: quantize(note.on_velocity, self.volume_steps),
+                detune: self.quantize_pitch(note, frequency_lut)?,
+                ..note.clone()
+            }));
+        }
+        Ok(DriverOutput {
+            sequence: Sequence {
+                notes: played.into_iter().flatten().collect(),
+                loop_info: sorted.loop_info,
+            },
+            allocations,
+        })
+    }
+    /// Picks the voice to steal when every voice is busy, following the stealing policy
+    fn pick_stolen_voice(&self, voices: &[Option<ActiveVoice>], priority: u8) -> Option<usize> {
+        let candidates = voices
+            .iter()
+            .enumerate()
+            .filter_map(|(id, v)| v.as_ref().map(|v| (id, v)));
+        match self.stealing {
+            VoiceStealing::Never => None,
+            VoiceStealing::Oldest => candidates.min_by_key(|(_, v)| v.start_tick).map(|(id, _)| id),
+            VoiceStealing::LowestPriority => candidates
+                .filter(|(_, v)| v.priority <= priority)
+                .min_by_key(|(_, v)| (v.priority, v.start_tick))
+                .map(|(id, _)| id),
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use Envelope;
+
+/// Attack, Decay, Sustain, Release Envelope
+#[derive(Clone)]
+pub struct AdsrEnvelope {
+    /// Time to go from silence to full loudness, in seconds
+    pub attack: f64,
+    /// Time to go from full loudness to the sustain level, in seconds
+    pub decay: f64,
+    /// Amplitude held while the key is pressed after the decay, between 0 and 1
+    pub sustain: f64,
+    /// Time to go to silence after the key is released, in seconds. The release starts from the level at the time of the release.
+    pub release: f64,
+}
+
+impl Envelope for AdsrEnvelope {
+    fn before_during_sustain(&self, time: &f64) -> f64 {
+        if *time < self.attack {
+            time / self.attack
+        } else if *time < self.attack + self.decay {
+            1f64 - (1f64 - self.sustain) * ((time - self.attack) / self.decay)
+        } else {
+            self.sustain
+        }
+    }
+    fn after_sustain(&self, time: &f64) -> f64 {
+        if *time < self.release {
+            self.sustain * (1f64 - time / self.release)
+        } else {
+            0f64
+        }
+    }
+    fn release_from(&self, time: &f64, level: f64) -> f64 {
+        if *time < self.release {
+            level * (1f64 - time / self.release)
+        } else {
+            0f64
+        }
+    }
+    fn release_duration(&self) -> f64 {
+        self.release
+    }
+}
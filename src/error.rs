@@ -1,33 +1,115 @@
+use export::FileFormat;
 use pcm::error::PCMError;
+use render::{RenderLimit, RenderReport};
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result};
+use std::io;
+use {FrequencyId, InstrumentId, NoteId};
 
 /// The main error type. Everything in this library that returns an error will return this type.
 #[derive(Debug)]
 pub enum SequencerError {
     /// An error originating from the PCM Library
     PCMError(PCMError),
+    /// An error while reading or writing a file
+    IOError(io::Error),
+    /// If the extension of a file does not match any supported format
+    UnknownFileFormat(String),
+    /// If a format is supported but its feature is not enabled
+    FormatNotEnabled(FileFormat),
+    /// An error originating from an audio encoder
+    EncoderError(String),
+    /// If there is no ExportProfile with this name
+    UnknownExportProfile(String),
+    /// If a line of a text Sequence cannot be read, with the line number and what is wrong
+    ParseError(usize, String),
+    /// If a script fails to compile or to run
+    ScriptError(String),
+    /// If an instrument definition file is not valid TOML or does not describe Instruments correctly
+    InstrumentFileError(String),
+    /// If there is no Key Generator with this name in a Registry
+    UnknownGenerator(String),
+    /// If there is no Envelope with this name in a Registry
+    UnknownEnvelope(String),
+    /// If a parameter given to a factory is missing or does not have the expected type
+    InvalidParameter(String),
     /// If no key is available and no custom KeyGenerator is provided
     NoDefaultKeyGiven,
     /// If a float given to use as a TIme or a Frequency is not a normal number and strictly superior to zero
     ImpossibleTimeOrFrequency(f64),
+    /// If frames are resampled with a step that is not finite and strictly positive
+    InvalidResampleStep(f64),
     /// If there is no frequency associated with an ID in a FrequencyLookupTable
-    NoFrequencyForID(usize),
+    NoFrequencyForID(FrequencyId),
     /// If there is no instrument associated with an ID in a InstrumentTable
-    NoInstrumentForID(usize),
+    NoInstrumentForID(InstrumentId),
     /// IF there is no key associated with an ID for an Instrument
-    NoKeyForID(usize),
+    NoKeyForID(FrequencyId),
+    /// If two sequencers that need to be mixed together do not have the same sample rate and amount of channels
+    MismatchedParameters,
+    /// If an album is rendered without any song
+    EmptyAlbum,
+    /// If a render goes past one of its RenderLimits, with what was done until then
+    RenderLimitExceeded(RenderLimit, Box<RenderReport>),
+    /// If a key has no frame to play, with the ID of the key
+    EmptyKey(FrequencyId),
+    /// If a time is negative, not finite or past MAX_SONG_LENGTH when converting it to frames
+    TimeOutOfRange(f64),
+    /// If a Note of a Sequence is not valid after changing it, with the index of the note and what is wrong
+    InvalidNote(NoteId, String),
+    /// If a note is outside of the KeyRange of an Instrument that does not accept it
+    FrequencyOutOfRange(f64),
+    /// If a Sample is not a Float, the only type supported for now
+    UnsupportedSampleType,
+    /// If a Frame does not have a sample for a channel
+    NoChannel(usize),
+    /// If a mixed sample goes outside of [-1, 1], only checked with the `strict-checks` feature
+    AmplitudeOutOfRange(f64),
+    /// If an Envelope gives an amplitude outside of [0, 1], only checked with the `strict-checks` feature
+    EnvelopeOutOfRange(f64),
+    /// If a frame position went backwards during render, only checked with the `strict-checks` feature
+    NonMonotonicFramePosition(usize),
 }
 
 impl Error for SequencerError {
     fn description(&self) -> &str {
         match self {
             SequencerError::PCMError(e) => e.description(),
+            SequencerError::IOError(_) => "Reading or writing a file failed",
+            SequencerError::UnknownFileFormat(_) => "The file extension does not match any supported format",
+            SequencerError::FormatNotEnabled(_) => "The feature for this file format is not enabled",
+            SequencerError::EncoderError(_) => "An audio encoder failed",
+            SequencerError::UnknownExportProfile(_) => "There is no export profile with this name",
+            SequencerError::ParseError(_, _) => "A line of a text Sequence cannot be read",
+            SequencerError::ScriptError(_) => "A script failed to compile or to run",
+            SequencerError::InstrumentFileError(_) => "An instrument definition file is not valid",
+            SequencerError::UnknownGenerator(_) => "There is no Key Generator with this name in the Registry",
+            SequencerError::UnknownEnvelope(_) => "There is no Envelope with this name in the Registry",
+            SequencerError::InvalidParameter(_) => "A parameter is missing or does not have the expected type",
             SequencerError::NoDefaultKeyGiven => "No KeyGenerator and no default key to change the pitch of",
             SequencerError::ImpossibleTimeOrFrequency(_) => "An impossible value for a Frequency or a Time was tried to be used or put in a FrequencyLookupTable",
+            SequencerError::InvalidResampleStep(_) => "Frames can only be resampled with a finite and strictly positive step",
             SequencerError::NoFrequencyForID(_) => "There is no frequency in the FrequencyLookupTable associated with this ID",
             SequencerError::NoInstrumentForID(_) => "There is no instrument in the InstrumentLookingTable associated with this ID",
-            SequencerError::NoKeyForID(_) => "There is no Key in the Instrument associated with this ID"
+            SequencerError::NoKeyForID(_) => "There is no Key in the Instrument associated with this ID",
+            SequencerError::MismatchedParameters => "The sequencers do not have the same sample rate and amount of channels",
+            SequencerError::EmptyAlbum => "An album needs at least one song",
+            SequencerError::RenderLimitExceeded(_, _) => "A render went past one of its limits",
+            SequencerError::EmptyKey(_) => "A key has no frame to play",
+            SequencerError::TimeOutOfRange(_) => "A time is negative, not finite or past the maximum song length",
+            SequencerError::InvalidNote(_, _) => "A Note is not valid after changing it",
+            SequencerError::FrequencyOutOfRange(_) => "A note is outside of the KeyRange of its Instrument",
+            SequencerError::UnsupportedSampleType => "Only Float samples are supported",
+            SequencerError::NoChannel(_) => "A Frame has no sample for this channel",
+            SequencerError::AmplitudeOutOfRange(_) => "A mixed sample went outside of [-1, 1] before the clip stage",
+            SequencerError::EnvelopeOutOfRange(_) => "An Envelope gave an amplitude outside of [0, 1]",
+            SequencerError::NonMonotonicFramePosition(_) => "A frame position went backwards during render"
+        }
+    }
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SequencerError::IOError(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -36,13 +118,47 @@ impl Display for SequencerError {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
             SequencerError::PCMError(e) => e.fmt(f),
+            SequencerError::IOError(e) => e.fmt(f),
+            SequencerError::UnknownFileFormat(e) => write!(f, "Unknown file format: {}", e),
+            SequencerError::FormatNotEnabled(format) => {
+                write!(f, "Feature not enabled for format: {:?}", format)
+            }
+            SequencerError::EncoderError(e) => write!(f, "Encoder error: {}", e),
+            SequencerError::UnknownExportProfile(p) => write!(f, "Unknown export profile: {}", p),
+            SequencerError::ParseError(line, e) => write!(f, "Parse error on line {}: {}", line, e),
+            SequencerError::ScriptError(e) => write!(f, "Script error: {}", e),
+            SequencerError::InstrumentFileError(e) => write!(f, "Instrument file error: {}", e),
+            SequencerError::UnknownGenerator(n) => write!(f, "Unknown Key Generator: {}", n),
+            SequencerError::UnknownEnvelope(n) => write!(f, "Unknown Envelope: {}", n),
+            SequencerError::InvalidParameter(e) => write!(f, "Invalid parameter: {}", e),
             SequencerError::NoDefaultKeyGiven => {
                 write!(f, "No key in vec, impossible to crate new keys")
             }
             SequencerError::ImpossibleTimeOrFrequency(v) => write!(f, "Impossible value: {}", v),
+            SequencerError::InvalidResampleStep(s) => write!(f, "Invalid resample step: {}", s),
             SequencerError::NoFrequencyForID(id) => write!(f, "Unassigned Frequency ID: {}", id),
             SequencerError::NoInstrumentForID(id) => write!(f, "Unassigned Instrument ID: {}", id),
             SequencerError::NoKeyForID(id) => write!(f, "Unassigned Key ID: {}", id),
+            SequencerError::MismatchedParameters => {
+                write!(f, "Sample rates or amounts of channels do not match")
+            }
+            SequencerError::EmptyAlbum => write!(f, "No song in the album"),
+            SequencerError::RenderLimitExceeded(limit, _) => {
+                write!(f, "Render limit exceeded: {:?}", limit)
+            }
+            SequencerError::EmptyKey(id) => write!(f, "Empty key: {}", id),
+            SequencerError::TimeOutOfRange(t) => write!(f, "Time out of range: {}", t),
+            SequencerError::InvalidNote(id, e) => write!(f, "Invalid Note {}: {}", id, e),
+            SequencerError::FrequencyOutOfRange(v) => write!(f, "Frequency out of range: {}", v),
+            SequencerError::UnsupportedSampleType => write!(f, "Unsupported sample type"),
+            SequencerError::NoChannel(c) => write!(f, "No sample for channel: {}", c),
+            SequencerError::AmplitudeOutOfRange(v) => write!(f, "Amplitude out of range: {}", v),
+            SequencerError::EnvelopeOutOfRange(v) => {
+                write!(f, "Envelope amplitude out of range: {}", v)
+            }
+            SequencerError::NonMonotonicFramePosition(p) => {
+                write!(f, "Frame position went backwards: {}", p)
+            }
         }
     }
 }
@@ -52,3 +168,9 @@ impl From<PCMError> for SequencerError {
         SequencerError::PCMError(e)
     }
 }
+
+impl From<io::Error> for SequencerError {
+    fn from(e: io::Error) -> SequencerError {
+        SequencerError::IOError(e)
+    }
+}
@@ -0,0 +1,487 @@
+use adapters::SampleAccess;
+use analysis;
+use error::SequencerError;
+use filters::Biquad;
+use pcm::{Frame, PCMParameters, Sample, PCM};
+use pitch;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use Result;
+
+/// Audio file formats rendered audio can be written to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileFormat {
+    /// WAV, always available
+    Wav,
+    /// FLAC, needs the `flac` feature
+    Flac,
+    /// Ogg Vorbis, needs the `ogg` feature
+    Ogg,
+    /// MP3, needs the `mp3` feature
+    Mp3,
+}
+
+impl FileFormat {
+    /// Picks the format from the extension of a file, ignoring case
+    pub fn from_path(path: &Path) -> Result<FileFormat> {
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(e) => e.to_lowercase(),
+            None => return Err(SequencerError::UnknownFileFormat(String::new())),
+        };
+        match extension.as_str() {
+            "wav" | "wave" => Ok(FileFormat::Wav),
+            "flac" => Ok(FileFormat::Flac),
+            "ogg" | "oga" => Ok(FileFormat::Ogg),
+            "mp3" => Ok(FileFormat::Mp3),
+            _ => Err(SequencerError::UnknownFileFormat(extension)),
+        }
+    }
+}
+
+/// Sample format of lossless files
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BitDepth {
+    /// 16-bit integer
+    Int16,
+    /// 24-bit integer
+    Int24,
+    /// 32-bit float, only for WAV. FLAC uses 24-bit instead.
+    Float32,
+}
+
+/// Settings used by encoders, each format only uses the settings that apply to it
+#[derive(Clone, Copy, Debug)]
+pub struct EncodeSettings {
+    /// Sample format for WAV and FLAC
+    pub bit_depth: BitDepth,
+    /// Quality between 0 and 1 for Ogg Vorbis and MP3
+    pub quality: f32,
+}
+
+impl Default for EncodeSettings {
+    fn default() -> EncodeSettings {
+        EncodeSettings {
+            bit_depth: BitDepth::Int16,
+            quality: 0.5f32,
+        }
+    }
+}
+
+impl BitDepth {
+    /// Amount of bits used by each sample
+    pub fn bits(self) -> u16 {
+        match self {
+            BitDepth::Int16 => 16,
+            BitDepth::Int24 => 24,
+            BitDepth::Float32 => 32,
+        }
+    }
+}
+
+/// Writes a PCM to a file, the format is picked from the extension
+pub fn write_file<P: AsRef<Path>>(pcm: &PCM, path: P) -> Result<()> {
+    write_file_with_settings(pcm, path, &EncodeSettings::default())
+}
+
+/// Writes a PCM to a file with specific encoder settings, the format is picked from the extension
+pub fn write_file_with_settings<P: AsRef<Path>>(
+    pcm: &PCM,
+    path: P,
+    settings: &EncodeSettings,
+) -> Result<()> {
+    let format = FileFormat::from_path(path.as_ref())?;
+    let mut encoded = Vec::new();
+    encode_with_settings(pcm, format, settings, &mut encoded)?;
+    write_encoded(&encoded, path)
+}
+
+/// Writes encoded data to a file. Encoding is done beforehand so that a failure does not leave a truncated file behind.
+fn write_encoded<P: AsRef<Path>>(encoded: &[u8], path: P) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(encoded)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Encodes a PCM in a particular format with the default settings
+pub fn encode<W: Write>(pcm: &PCM, format: FileFormat, writer: &mut W) -> Result<()> {
+    encode_with_settings(pcm, format, &EncodeSettings::default(), writer)
+}
+
+/// Encodes a PCM in a particular format
+pub fn encode_with_settings<W: Write>(
+    pcm: &PCM,
+    format: FileFormat,
+    settings: &EncodeSettings,
+    writer: &mut W,
+) -> Result<()> {
+    match format {
+        FileFormat::Wav => encode_wav_with_bit_depth(pcm, settings.bit_depth, writer),
+        FileFormat::Flac => encode_flac_with_bit_depth(pcm, settings.bit_depth, writer),
+        FileFormat::Ogg => encode_ogg_with_quality(pcm, settings.quality, writer),
+        FileFormat::Mp3 => encode_mp3_with_quality(pcm, settings.quality, writer),
+    }
+}
+
+/// Converts a float sample to a signed integer sample of a certain amount of bits, clipping it if needed
+fn to_int(sample: f32, bits: u16) -> i32 {
+    let max = ((1i64 << (bits - 1)) - 1) as f64;
+    (f64::from(sample.clamp(-1f32, 1f32)) * max).round() as i32
+}
+
+/// Encodes a PCM as a 16-bit WAV
+pub fn encode_wav<W: Write>(pcm: &PCM, writer: &mut W) -> Result<()> {
+    encode_wav_with_bit_depth(pcm, BitDepth::Int16, writer)
+}
+
+/// Encodes a PCM as a WAV with a specific sample format
+pub fn encode_wav_with_bit_depth<W: Write>(
+    pcm: &PCM,
+    bit_depth: BitDepth,
+    writer: &mut W,
+) -> Result<()> {
+    let samples = pcm.interleaved_f32()?;
+    let nb_channels = pcm.parameters.nb_channels;
+    let sample_rate = pcm.parameters.sample_rate;
+    let bytes_per_sample = u32::from(bit_depth.bits() / 8);
+    let block_align = u32::from(nb_channels) * bytes_per_sample;
+    let too_long = || SequencerError::EncoderError(String::from("Too much audio for a WAV file"));
+    let data_size = u32::try_from(samples.len())
+        .ok()
+        .and_then(|n| n.checked_mul(bytes_per_sample))
+        .ok_or_else(too_long)?;
+    // Chunks have an even size, a pad byte follows odd-sized data like 24-bit mono with an odd amount of frames
+    let pad = data_size % 2;
+    let riff_size = data_size.checked_add(36 + pad).ok_or_else(too_long)?;
+    let byte_rate = sample_rate
+        .checked_mul(block_align)
+        .ok_or(SequencerError::EncoderError(String::from("Sample rate too high for a WAV file")))?;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&riff_size.to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // Size of the fmt chunk
+    let format_tag: u16 = match bit_depth {
+        BitDepth::Float32 => 3, // IEEE float
+        _ => 1,                 // Integer PCM
+    };
+    writer.write_all(&format_tag.to_le_bytes())?;
+    writer.write_all(&nb_channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&byte_rate.to_le_bytes())?;
+    writer.write_all(&(block_align as u16).to_le_bytes())?;
+    writer.write_all(&bit_depth.bits().to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        match bit_depth {
+            BitDepth::Int16 => writer.write_all(&(to_int(sample, 16) as i16).to_le_bytes())?,
+            BitDepth::Int24 => writer.write_all(&to_int(sample, 24).to_le_bytes()[..3])?,
+            BitDepth::Float32 => writer.write_all(&sample.to_le_bytes())?,
+        }
+    }
+    if pad == 1 {
+        writer.write_all(&[0u8])?;
+    }
+    Ok(())
+}
+
+/// Encodes a PCM as a 16-bit FLAC
+pub fn encode_flac<W: Write>(pcm: &PCM, writer: &mut W) -> Result<()> {
+    encode_flac_with_bit_depth(pcm, BitDepth::Int16, writer)
+}
+
+/// Encodes a PCM as a 16 or 24-bit FLAC
+#[cfg(feature = "flac")]
+pub fn encode_flac_with_bit_depth<W: Write>(
+    pcm: &PCM,
+    bit_depth: BitDepth,
+    writer: &mut W,
+) -> Result<()> {
+    use flacenc::component::BitRepr;
+    use flacenc::error::Verify;
+    let bits = match bit_depth {
+        BitDepth::Int16 => 16,
+        _ => 24,
+    };
+    let samples: Vec<i32> = pcm.interleaved_f32()?
+        .into_iter()
+        .map(|s| to_int(s, bits))
+        .collect();
+    let config = match flacenc::config::Encoder::default().into_verified() {
+        Ok(c) => c,
+        Err((_, e)) => return Err(SequencerError::EncoderError(e.to_string())),
+    };
+    let source = flacenc::source::MemSource::from_samples(
+        &samples,
+        pcm.parameters.nb_channels as usize,
+        bits as usize,
+        pcm.parameters.sample_rate as usize,
+    );
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream
+        .write(&mut sink)
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    writer.write_all(sink.as_slice())?;
+    Ok(())
+}
+
+#[cfg(not(feature = "flac"))]
+pub fn encode_flac_with_bit_depth<W: Write>(
+    _pcm: &PCM,
+    _bit_depth: BitDepth,
+    _writer: &mut W,
+) -> Result<()> {
+    Err(SequencerError::FormatNotEnabled(FileFormat::Flac))
+}
+
+/// Cutoff of the anti-aliasing filter used when lowering the sample rate, as a fraction of the new sample rate.
+/// Just below the Nyquist frequency of 0.5, leaving room for the slope of the filter.
+const ANTI_ALIAS_CUTOFF: f64 = 0.45f64;
+/// Quality factors of the sections of the anti-aliasing filter, giving an 8th order Butterworth low pass
+const ANTI_ALIAS_Q: [f64; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+
+/// Low passes Float samples below the Nyquist frequency of a lower sample rate, so that resampling to it does not alias
+fn anti_alias(frames: &[Frame], sample_rate: u32, target_rate: u32) -> Vec<Frame> {
+    let cutoff = ANTI_ALIAS_CUTOFF * f64::from(target_rate);
+    let nb_channels = frames.iter().map(|f| f.samples.len()).max().unwrap_or(0);
+    let mut filters: Vec<Vec<Biquad>> = (0..nb_channels)
+        .map(|_| {
+            ANTI_ALIAS_Q
+                .iter()
+                .map(|q| Biquad::low_pass(cutoff, *q, f64::from(sample_rate)))
+                .collect()
+        })
+        .collect();
+    frames
+        .iter()
+        .map(|frame| Frame {
+            samples: frame
+                .samples
+                .iter()
+                .zip(filters.iter_mut())
+                .map(|(sample, sections)| match *sample {
+                    Sample::Float(s) => Sample::Float(
+                        sections
+                            .iter_mut()
+                            .fold(f64::from(s), |value, section| section.process(value))
+                            as f32,
+                    ),
+                    ref other => other.clone(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Amount of frames given to the Vorbis encoder at once
+#[cfg(feature = "ogg")]
+const VORBIS_BLOCK_SIZE: usize = 1024;
+
+/// Encodes a PCM as Ogg Vorbis at quality 5
+pub fn encode_ogg<W: Write>(pcm: &PCM, writer: &mut W) -> Result<()> {
+    encode_ogg_with_quality(pcm, 0.5f32, writer)
+}
+
+/// Encodes a PCM as Ogg Vorbis with a quality between 0 and 1
+#[cfg(feature = "ogg")]
+pub fn encode_ogg_with_quality<W: Write>(pcm: &PCM, quality: f32, writer: &mut W) -> Result<()> {
+    use std::num::{NonZeroU32, NonZeroU8};
+    use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoderBuilder};
+    let channels = pcm.deinterleaved_f32()?;
+    let sample_rate = NonZeroU32::new(pcm.parameters.sample_rate)
+        .ok_or(SequencerError::EncoderError(String::from("Sample rate of 0")))?;
+    let nb_channels = NonZeroU8::new(pcm.parameters.nb_channels as u8)
+        .ok_or(SequencerError::EncoderError(String::from("No channels")))?;
+    let mut encoder = VorbisEncoderBuilder::new_with_serial(sample_rate, nb_channels, writer, 0)
+        .bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr {
+            target_quality: quality,
+        })
+        .build()
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    let nb_frames = pcm.frames.len();
+    let mut start = 0usize;
+    while start < nb_frames {
+        let end = (start + VORBIS_BLOCK_SIZE).min(nb_frames);
+        let block: Vec<&[f32]> = channels.iter().map(|c| &c[start..end]).collect();
+        encoder
+            .encode_audio_block(&block)
+            .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+        start = end;
+    }
+    encoder
+        .finish()
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "ogg"))]
+pub fn encode_ogg_with_quality<W: Write>(_pcm: &PCM, _quality: f32, _writer: &mut W) -> Result<()> {
+    Err(SequencerError::FormatNotEnabled(FileFormat::Ogg))
+}
+
+/// Encodes a mono or stereo PCM as a 192kbps MP3
+pub fn encode_mp3<W: Write>(pcm: &PCM, writer: &mut W) -> Result<()> {
+    encode_mp3_with_quality(pcm, 0.5f32, writer)
+}
+
+/// Encodes a mono or stereo PCM as MP3, the quality between 0 and 1 picks the bitrate from 96 to 320kbps
+#[cfg(feature = "mp3")]
+pub fn encode_mp3_with_quality<W: Write>(pcm: &PCM, quality: f32, writer: &mut W) -> Result<()> {
+    use mp3lame_encoder::{
+        max_required_buffer_size, Bitrate, Builder, FlushNoGap, InterleavedPcm, MonoPcm,
+        Quality,
+    };
+    let samples = pcm.interleaved_f32()?;
+    let mut builder = Builder::new()
+        .ok_or(SequencerError::EncoderError(String::from("Cannot create LAME encoder")))?;
+    builder
+        .set_num_channels(pcm.parameters.nb_channels as u8)
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    builder
+        .set_sample_rate(pcm.parameters.sample_rate)
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    builder
+        .set_brate(if quality < 0.2f32 {
+            Bitrate::Kbps96
+        } else if quality < 0.4f32 {
+            Bitrate::Kbps128
+        } else if quality < 0.6f32 {
+            Bitrate::Kbps192
+        } else if quality < 0.8f32 {
+            Bitrate::Kbps256
+        } else {
+            Bitrate::Kbps320
+        })
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    builder
+        .set_quality(Quality::Best)
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    let mut encoder = builder
+        .build()
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    let mut out = Vec::with_capacity(max_required_buffer_size(samples.len()));
+    match pcm.parameters.nb_channels {
+        1 => encoder.encode_to_vec(MonoPcm(&samples[..]), &mut out),
+        2 => encoder.encode_to_vec(InterleavedPcm(&samples[..]), &mut out),
+        _ => {
+            return Err(SequencerError::EncoderError(String::from(
+                "MP3 only supports mono and stereo",
+            )))
+        }
+    }.map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    out.reserve(max_required_buffer_size(0));
+    encoder
+        .flush_to_vec::<FlushNoGap>(&mut out)
+        .map_err(|e| SequencerError::EncoderError(e.to_string()))?;
+    writer.write_all(&out)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "mp3"))]
+pub fn encode_mp3_with_quality<W: Write>(_pcm: &PCM, _quality: f32, _writer: &mut W) -> Result<()> {
+    Err(SequencerError::FormatNotEnabled(FileFormat::Mp3))
+}
+
+/// Everything needed to turn rendered audio into a finished file in one call
+#[derive(Clone, Debug)]
+pub struct ExportProfile {
+    /// Sample rate of the file, the audio is resampled if needed. If not set, the rendered sample rate is kept.
+    pub sample_rate: Option<u32>,
+    /// Integrated loudness of the file in LUFS. If not set, the level is left as is.
+    pub loudness: Option<f64>,
+    /// Format of the file
+    pub format: FileFormat,
+    /// Settings of the encoder
+    pub settings: EncodeSettings,
+}
+
+impl ExportProfile {
+    /// Gives a pre-made profile from its name:
+    /// * `game-asset`: 48kHz 16-bit WAV at -16 LUFS
+    /// * `preview`: 44.1kHz Ogg Vorbis at quality 5
+    /// * `master`: 32-bit float WAV at the rendered sample rate and level
+    pub fn by_name(name: &str) -> Result<ExportProfile> {
+        match name {
+            "game-asset" => Ok(ExportProfile {
+                sample_rate: Some(48_000),
+                loudness: Some(-16f64),
+                format: FileFormat::Wav,
+                settings: EncodeSettings {
+                    bit_depth: BitDepth::Int16,
+                    quality: 0.5f32,
+                },
+            }),
+            "preview" => Ok(ExportProfile {
+                sample_rate: Some(44_100),
+                loudness: None,
+                format: FileFormat::Ogg,
+                settings: EncodeSettings {
+                    bit_depth: BitDepth::Int16,
+                    quality: 0.5f32,
+                },
+            }),
+            "master" => Ok(ExportProfile {
+                sample_rate: None,
+                loudness: None,
+                format: FileFormat::Wav,
+                settings: EncodeSettings {
+                    bit_depth: BitDepth::Float32,
+                    quality: 1f32,
+                },
+            }),
+            _ => Err(SequencerError::UnknownExportProfile(String::from(name))),
+        }
+    }
+    /// Resamples and normalizes a PCM following this profile. The audio is low passed before lowering its sample rate.
+    pub fn process(&self, pcm: &PCM) -> Result<PCM> {
+        let mut processed = match self.sample_rate {
+            Some(rate) if rate != pcm.parameters.sample_rate => {
+                let filtered;
+                let frames = if rate < pcm.parameters.sample_rate {
+                    filtered = anti_alias(&pcm.frames, pcm.parameters.sample_rate, rate);
+                    &filtered[..]
+                } else {
+                    &pcm.frames[..]
+                };
+                PCM {
+                    parameters: PCMParameters {
+                        sample_rate: rate,
+                        ..pcm.parameters.clone()
+                    },
+                    loop_info: None,
+                    frames: pitch::resample_frames(
+                        frames,
+                        f64::from(pcm.parameters.sample_rate) / f64::from(rate),
+                        pcm.parameters.nb_channels as usize,
+                    )?,
+                }
+            }
+            _ => pcm.clone(),
+        };
+        if let Some(target) = self.loudness {
+            processed = analysis::normalize_loudness(&processed, target)?;
+        }
+        Ok(processed)
+    }
+    /// Processes a PCM and writes it to a file in the format of this profile.
+    /// Gives an error if the extension of the path is for another format.
+    pub fn write_file<P: AsRef<Path>>(&self, pcm: &PCM, path: P) -> Result<()> {
+        let format = FileFormat::from_path(path.as_ref())?;
+        if format != self.format {
+            return Err(SequencerError::InvalidParameter(format!(
+                "the extension of {} is for {:?} files but the profile writes {:?}",
+                path.as_ref().display(),
+                format,
+                self.format
+            )));
+        }
+        let processed = self.process(pcm)?;
+        let mut encoded = Vec::new();
+        encode_with_settings(&processed, self.format, &self.settings, &mut encoded)?;
+        write_encoded(&encoded, path)
+    }
+}
@@ -0,0 +1,138 @@
+use std::f64::consts::PI;
+
+/// Second order IIR filter, in Direct Form I
+#[derive(Clone)]
+pub struct Biquad {
+    /// Feedforward coefficients, normalized
+    pub b: [f64; 3],
+    /// Feedback coefficients a1 and a2, normalized so that a0 is 1
+    pub a: [f64; 2],
+    /// Last two inputs
+    x: [f64; 2],
+    /// Last two outputs
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// Creates a filter from raw coefficients, normalizing them by a0
+    pub fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b: [b0 / a0, b1 / a0, b2 / a0],
+            a: [a1 / a0, a2 / a0],
+            x: [0f64; 2],
+            y: [0f64; 2],
+        }
+    }
+    /// High shelf filter
+    /// # Arguments
+    /// * frequency: Corner frequency in Hertz
+    /// * gain: Gain of the shelf in dB
+    /// * q: Quality factor
+    /// * sample_rate: Sample rate in Hertz
+    pub fn high_shelf(frequency: f64, gain: f64, q: f64, sample_rate: f64) -> Biquad {
+        let a = 10f64.powf(gain / 40f64);
+        let w0 = 2f64 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2f64 * q);
+        let cos = w0.cos();
+        let sqrt_a_alpha = 2f64 * a.sqrt() * alpha;
+        Biquad::new(
+            a * ((a + 1f64) + (a - 1f64) * cos + sqrt_a_alpha),
+            -2f64 * a * ((a - 1f64) + (a + 1f64) * cos),
+            a * ((a + 1f64) + (a - 1f64) * cos - sqrt_a_alpha),
+            (a + 1f64) - (a - 1f64) * cos + sqrt_a_alpha,
+            2f64 * ((a - 1f64) - (a + 1f64) * cos),
+            (a + 1f64) - (a - 1f64) * cos - sqrt_a_alpha,
+        )
+    }
+    /// Low shelf filter
+    /// # Arguments
+    /// * frequency: Corner frequency in Hertz
+    /// * gain: Gain of the shelf in dB
+    /// * q: Quality factor
+    /// * sample_rate: Sample rate in Hertz
+    pub fn low_shelf(frequency: f64, gain: f64, q: f64, sample_rate: f64) -> Biquad {
+        let a = 10f64.powf(gain / 40f64);
+        let w0 = 2f64 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2f64 * q);
+        let cos = w0.cos();
+        let sqrt_a_alpha = 2f64 * a.sqrt() * alpha;
+        Biquad::new(
+            a * ((a + 1f64) - (a - 1f64) * cos + sqrt_a_alpha),
+            2f64 * a * ((a - 1f64) - (a + 1f64) * cos),
+            a * ((a + 1f64) - (a - 1f64) * cos - sqrt_a_alpha),
+            (a + 1f64) + (a - 1f64) * cos + sqrt_a_alpha,
+            -2f64 * ((a - 1f64) + (a + 1f64) * cos),
+            (a + 1f64) + (a - 1f64) * cos - sqrt_a_alpha,
+        )
+    }
+    /// Peaking filter, boosting or cutting a band around a frequency
+    /// # Arguments
+    /// * frequency: Center frequency in Hertz
+    /// * gain: Gain at the center frequency in dB
+    /// * q: Quality factor, higher values give a narrower band
+    /// * sample_rate: Sample rate in Hertz
+    pub fn peaking(frequency: f64, gain: f64, q: f64, sample_rate: f64) -> Biquad {
+        let a = 10f64.powf(gain / 40f64);
+        let w0 = 2f64 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2f64 * q);
+        let cos = w0.cos();
+        Biquad::new(
+            1f64 + alpha * a,
+            -2f64 * cos,
+            1f64 - alpha * a,
+            1f64 + alpha / a,
+            -2f64 * cos,
+            1f64 - alpha / a,
+        )
+    }
+    /// Low pass filter
+    /// # Arguments
+    /// * frequency: Cutoff frequency in Hertz
+    /// * q: Quality factor
+    /// * sample_rate: Sample rate in Hertz
+    pub fn low_pass(frequency: f64, q: f64, sample_rate: f64) -> Biquad {
+        let w0 = 2f64 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2f64 * q);
+        let cos = w0.cos();
+        Biquad::new(
+            (1f64 - cos) / 2f64,
+            1f64 - cos,
+            (1f64 - cos) / 2f64,
+            1f64 + alpha,
+            -2f64 * cos,
+            1f64 - alpha,
+        )
+    }
+    /// High pass filter
+    /// # Arguments
+    /// * frequency: Cutoff frequency in Hertz
+    /// * q: Quality factor
+    /// * sample_rate: Sample rate in Hertz
+    pub fn high_pass(frequency: f64, q: f64, sample_rate: f64) -> Biquad {
+        let w0 = 2f64 * PI * frequency / sample_rate;
+        let alpha = w0.sin() / (2f64 * q);
+        let cos = w0.cos();
+        Biquad::new(
+            (1f64 + cos) / 2f64,
+            -(1f64 + cos),
+            (1f64 + cos) / 2f64,
+            1f64 + alpha,
+            -2f64 * cos,
+            1f64 - alpha,
+        )
+    }
+    /// Filters one sample
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+    /// Clears the memory of the filter
+    pub fn reset(&mut self) {
+        self.x = [0f64; 2];
+        self.y = [0f64; 2];
+    }
+}
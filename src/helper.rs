@@ -1,22 +1,36 @@
+use pitch::{self, Scale};
 use std::collections::HashMap;
 use std::f64::EPSILON;
-use {FrequencyLookupTable, Note, Sequence};
+use {FrequencyId, FrequencyLookupTable, InstrumentId, Note, Sequence};
 
 /// Represents a Note missing some information
 #[derive(Clone)]
 pub struct PartialNote {
     pub start_at: f64,
     pub on_velocity: f64,
+    pub detune: f64,
+}
+
+/// Snaps the frequencies given to a SequenceHelper to the pitches of a scale, for cleaner FrequencyLookupTables from slightly off pitch data.
+/// What a frequency is moved by is kept as the detune of its note, so the notes still play at the original frequency.
+#[derive(Clone)]
+pub struct PitchSnapping {
+    /// Pitches to snap to
+    pub scale: Scale,
+    /// Largest distance in cents a frequency is moved by, frequencies further from every pitch of the scale are kept as they are
+    pub tolerance: f64,
 }
 
 /// Helps creating a Sequence and a FrequencyLookupTable from another type of sequence
 #[derive(Default)]
 pub struct SequenceHelper {
-    pub current_instruments: HashMap<usize, HashMap<usize, PartialNote>>,
+    pub current_instruments: HashMap<InstrumentId, HashMap<FrequencyId, PartialNote>>,
     pub frequency_lut: Option<FrequencyLookupTable>,
     pub frequency_lut_builder: Option<Vec<f64>>,
     pub sequence: Sequence,
     pub at_time: f64,
+    /// Snaps frequencies given to the functions not taking a Frequency ID. If not set, frequencies are used as they are.
+    pub pitch_snapping: Option<PitchSnapping>,
 }
 
 impl SequenceHelper {
@@ -28,6 +42,7 @@ impl SequenceHelper {
             frequency_lut_builder: Some(Vec::new()),
             sequence: Sequence::new(),
             at_time: 0f64,
+            pitch_snapping: None,
         }
     }
     /// Creates a new empty HardwareSequenceHelper with a already existing FLUT
@@ -38,6 +53,7 @@ impl SequenceHelper {
             frequency_lut_builder: None,
             sequence: Sequence::new(),
             at_time: 0f64,
+            pitch_snapping: None,
         }
     }
     /// Makes the time go forward in seconds
@@ -49,25 +65,26 @@ impl SequenceHelper {
         self.at_time = 0f64;
     }
     /// When a new note starts in the sequence
-    pub fn start_note(&mut self, frequency: f64, on_velocity: f64, instrument_id: usize) {
-        let frequency_id = match &mut self.frequency_lut_builder {
-            Some(c) => match c.iter().position(|&x| (x - frequency).abs() < EPSILON) {
-                Some(i) => i,
-                None => {
-                    c.push(frequency);
-                    c.len() - 1
-                }
-            },
-            None => panic!("Deserved for not using the correct function !"),
-        };
-        self.start_note_with_flut(frequency_id, on_velocity, instrument_id);
+    pub fn start_note(&mut self, frequency: f64, on_velocity: f64, instrument_id: InstrumentId) {
+        let (frequency_id, detune) = self.add_frequency(frequency);
+        self.start_detuned_note(frequency_id, on_velocity, instrument_id, detune);
     }
     /// When a new note starts in the sequence and the Frequency ID is already known
     pub fn start_note_with_flut(
         &mut self,
-        frequency_id: usize,
+        frequency_id: FrequencyId,
+        on_velocity: f64,
+        instrument_id: InstrumentId,
+    ) {
+        self.start_detuned_note(frequency_id, on_velocity, instrument_id, 0f64);
+    }
+    /// Starts a note with a known Frequency ID and detune
+    fn start_detuned_note(
+        &mut self,
+        frequency_id: FrequencyId,
         on_velocity: f64,
-        instrument_id: usize,
+        instrument_id: InstrumentId,
+        detune: f64,
     ) {
         let freq_hashmap = self.current_instruments
             .entry(instrument_id)
@@ -80,6 +97,7 @@ impl SequenceHelper {
                     PartialNote {
                         start_at: self.at_time,
                         on_velocity,
+                        detune,
                     },
                 );
             }
@@ -87,10 +105,11 @@ impl SequenceHelper {
         }
     }
     /// Stops the note
-    pub fn stop_note(&mut self, frequency: f64, off_velocity: f64, instrument_id: usize) {
+    pub fn stop_note(&mut self, frequency: f64, off_velocity: f64, instrument_id: InstrumentId) {
+        let (frequency, _) = self.snap(frequency);
         let frequency_id = match self.frequency_lut_builder {
             Some(ref c) => match c.iter().position(|&x| (x - frequency).abs() < EPSILON) {
-                Some(i) => Some(i),
+                Some(i) => Some(FrequencyId(i)),
                 None => None,
             },
             None => panic!("Deserved for not using the correct function !"),
@@ -102,9 +121,9 @@ impl SequenceHelper {
     /// Stops the note with a known Frequency ID
     pub fn stop_note_with_flut(
         &mut self,
-        frequency_id: usize,
+        frequency_id: FrequencyId,
         off_velocity: f64,
-        instrument_id: usize,
+        instrument_id: InstrumentId,
     ) {
         let mut to_remove = false;
         match self.current_instruments.get_mut(&instrument_id) {
@@ -120,6 +139,7 @@ impl SequenceHelper {
                                 on_velocity: pn.on_velocity,
                                 off_velocity,
                                 instrument_id,
+                                detune: pn.detune,
                             });
                         } else if (self.at_time - pn.start_at) < 0f64 {
                             panic!("A note has a negative duration");
@@ -142,34 +162,45 @@ impl SequenceHelper {
         duration: f64,
         on_velocity: f64,
         off_velocity: f64,
-        instrument_id: usize,
+        instrument_id: InstrumentId,
     ) {
-        let frequency_id = match &mut self.frequency_lut_builder {
-            Some(c) => match c.iter().position(|&x| (x - frequency).abs() < EPSILON) {
-                Some(i) => i,
-                None => {
-                    c.push(frequency);
-                    c.len() - 1
-                }
-            },
-            None => panic!("Deserved for not using the correct function !"),
-        };
-        self.new_note_with_flut(
+        let (frequency_id, detune) = self.add_frequency(frequency);
+        self.add_note(
             frequency_id,
             duration,
             on_velocity,
             off_velocity,
             instrument_id,
+            detune,
         );
     }
     /// Adds a new note to the sequence with known Frequency ID
     pub fn new_note_with_flut(
         &mut self,
-        frequency_id: usize,
+        frequency_id: FrequencyId,
+        duration: f64,
+        on_velocity: f64,
+        off_velocity: f64,
+        instrument_id: InstrumentId,
+    ) {
+        self.add_note(
+            frequency_id,
+            duration,
+            on_velocity,
+            off_velocity,
+            instrument_id,
+            0f64,
+        );
+    }
+    /// Adds a new note to the sequence with known Frequency ID and detune
+    fn add_note(
+        &mut self,
+        frequency_id: FrequencyId,
         duration: f64,
         on_velocity: f64,
         off_velocity: f64,
-        instrument_id: usize,
+        instrument_id: InstrumentId,
+        detune: f64,
     ) {
         self.sequence.add_note(Note {
             start_at: self.at_time,
@@ -179,8 +210,31 @@ impl SequenceHelper {
             on_velocity,
             off_velocity,
             instrument_id,
+            detune,
         });
     }
+    /// Snaps a frequency if asked to, giving the frequency to use and the detune from it to the original frequency
+    fn snap(&self, frequency: f64) -> (f64, f64) {
+        match self.pitch_snapping {
+            Some(ref s) => pitch::snap_to_scale(frequency, &s.scale, s.tolerance),
+            None => (frequency, 0f64),
+        }
+    }
+    /// Snaps a frequency if asked to and gives its Frequency ID along with the detune, adding it to the table if needed
+    fn add_frequency(&mut self, frequency: f64) -> (FrequencyId, f64) {
+        let (frequency, detune) = self.snap(frequency);
+        let frequency_id = match &mut self.frequency_lut_builder {
+            Some(c) => match c.iter().position(|&x| (x - frequency).abs() < EPSILON) {
+                Some(i) => FrequencyId(i),
+                None => {
+                    c.push(frequency);
+                    FrequencyId(c.len() - 1)
+                }
+            },
+            None => panic!("Deserved for not using the correct function !"),
+        };
+        (frequency_id, detune)
+    }
     /// Returns the built sequence
     pub fn get_sequence(&self) -> Sequence {
         self.sequence.clone()
@@ -190,13 +244,7 @@ impl SequenceHelper {
         match self.frequency_lut {
             Some(ref f) => f.clone(),
             None => match self.frequency_lut_builder {
-                Some(ref fc) => {
-                    let mut lut = HashMap::new();
-                    for (index, value) in fc.iter().enumerate() {
-                        lut.insert(index, value.clone());
-                    }
-                    FrequencyLookupTable { lut }
-                }
+                Some(ref fc) => FrequencyLookupTable::from_frequencies(fc),
                 None => panic!("Deserved for not using the correct function !"),
             },
         }
@@ -0,0 +1,192 @@
+use error::SequencerError;
+use registry::{ParameterValue, Parameters, Registry};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use toml::{Table, Value};
+use {Instrument, InstrumentId, InstrumentTable, KeyRange, OutOfRangePolicy, Result};
+
+/// Reads Instruments from TOML.
+/// Each Instrument is an `[[instrument]]` entry with an `id`, a `generator` table and optionally an `envelope` table,
+/// both picking what to use with their `type` and giving its parameters in the other fields.
+/// `loopable`, `priority`, `legato_retrigger` and a `key_range` table with `low`, `high` and `out_of_range` can also be set.
+///
+/// ```toml
+/// [[instrument]]
+/// id = 0
+/// generator = { type = "square" }
+/// envelope = { type = "adsr", attack = 0.01, decay = 0.1, sustain = 0.7, release = 0.2 }
+/// ```
+///
+/// Generators and Envelopes are created by name from the default Registry.
+pub fn parse_instruments(source: &str) -> Result<InstrumentTable> {
+    parse_instruments_with_registry(source, &Registry::default())
+}
+
+/// Same as parse_instruments, with Generators and Envelopes created from a Registry that can contain ones from other crates
+pub fn parse_instruments_with_registry(
+    source: &str,
+    registry: &Registry,
+) -> Result<InstrumentTable> {
+    let table: Table = source
+        .parse()
+        .map_err(|e: toml::de::Error| SequencerError::InstrumentFileError(e.to_string()))?;
+    let mut instruments = HashMap::new();
+    let entries = match table.get("instrument") {
+        Some(Value::Array(a)) => a.as_slice(),
+        Some(_) => return Err(invalid("instrument", "an array of tables")),
+        None => &[],
+    };
+    for entry in entries {
+        let entry = match entry {
+            Value::Table(t) => t,
+            _ => return Err(invalid("instrument", "an array of tables")),
+        };
+        let id = match entry.get("id") {
+            Some(Value::Integer(i)) if *i >= 0 => *i as usize,
+            _ => return Err(invalid("id", "a positive integer")),
+        };
+        let key_generator = match entry.get("generator") {
+            Some(Value::Table(t)) => {
+                registry.create_generator(get_str(t, "type")?, &to_parameters(t)?)?
+            }
+            _ => return Err(invalid("generator", "a table")),
+        };
+        let envelope = match entry.get("envelope") {
+            Some(Value::Table(t)) => {
+                Some(registry.create_envelope(get_str(t, "type")?, &to_parameters(t)?)?)
+            }
+            Some(_) => return Err(invalid("envelope", "a table")),
+            None => None,
+        };
+        let key_range = match entry.get("key_range") {
+            Some(Value::Table(t)) => Some(parse_key_range(t)?),
+            Some(_) => return Err(invalid("key_range", "a table")),
+            None => None,
+        };
+        instruments.insert(
+            InstrumentId(id),
+            Instrument {
+                keys: HashMap::new(),
+                key_generator: Some(key_generator),
+                loopable: get_bool(entry, "loopable", true)?,
+                envelope,
+                auto_pan: None,
+                priority: match entry.get("priority") {
+                    Some(Value::Integer(i)) if (0..=255).contains(i) => *i as u8,
+                    Some(_) => return Err(invalid("priority", "an integer between 0 and 255")),
+                    None => 0,
+                },
+                low_bit: None,
+                legato_retrigger: get_bool(entry, "legato_retrigger", true)?,
+                key_range,
+                layers: Vec::new(),
+                velocity_to_amplitude: None,
+                playback: None,
+            },
+        );
+    }
+    Ok(InstrumentTable { instruments })
+}
+
+/// Converts a TOML table to Parameters for a factory, leaving out the `type` field
+fn to_parameters(table: &Table) -> Result<Parameters> {
+    let mut parameters = Parameters::new();
+    for (name, value) in table {
+        if name == "type" {
+            continue;
+        }
+        parameters.set(
+            name,
+            match value {
+                Value::Integer(i) => ParameterValue::Integer(*i),
+                Value::Float(f) => ParameterValue::Float(*f),
+                Value::Boolean(b) => ParameterValue::Boolean(*b),
+                Value::String(s) => ParameterValue::Text(s.clone()),
+                _ => return Err(invalid(name, "a number, a boolean or a string")),
+            },
+        );
+    }
+    Ok(parameters)
+}
+
+/// Creates the KeyRange described by a table, notes out of range are transposed if not said otherwise
+fn parse_key_range(table: &Table) -> Result<KeyRange> {
+    let parameters = to_parameters(table)?;
+    let range = KeyRange {
+        low: parameters.get_f64("low")?,
+        high: parameters.get_f64("high")?,
+        out_of_range: match table.get("out_of_range").and_then(Value::as_str) {
+            Some("transpose") | None => OutOfRangePolicy::Transpose,
+            Some("drop") => OutOfRangePolicy::Drop,
+            Some("error") => OutOfRangePolicy::Error,
+            Some(_) => return Err(invalid("out_of_range", "transpose, drop or error")),
+        },
+    };
+    range.check()?;
+    Ok(range)
+}
+
+/// Error for a field that is missing or does not have the expected type
+fn invalid(field: &str, expected: &str) -> SequencerError {
+    SequencerError::InstrumentFileError(format!("{} should be {}", field, expected))
+}
+
+/// Reads a required string
+fn get_str<'a>(table: &'a Table, field: &str) -> Result<&'a str> {
+    table
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid(field, "a string"))
+}
+
+/// Reads an optional boolean
+fn get_bool(table: &Table, field: &str, default: bool) -> Result<bool> {
+    match table.get(field) {
+        Some(Value::Boolean(b)) => Ok(*b),
+        Some(_) => Err(invalid(field, "a boolean")),
+        None => Ok(default),
+    }
+}
+
+/// An instrument definition file that can be reloaded when it changes on disk, for tweaking instruments while listening to the result
+pub struct InstrumentFile {
+    /// Path of the file
+    pub path: PathBuf,
+    /// Modification time of the file when it was last loaded, None if it was never loaded
+    modified: Option<SystemTime>,
+    /// Registry the Generators and Envelopes are created from
+    registry: Registry,
+}
+
+impl InstrumentFile {
+    /// Watches a file, nothing is read until load or reload_if_changed are called
+    pub fn new<P: AsRef<Path>>(path: P) -> InstrumentFile {
+        InstrumentFile::with_registry(path, Registry::default())
+    }
+    /// Same as new, with Generators and Envelopes created from a Registry that can contain ones from other crates
+    pub fn with_registry<P: AsRef<Path>>(path: P, registry: Registry) -> InstrumentFile {
+        InstrumentFile {
+            path: path.as_ref().to_path_buf(),
+            modified: None,
+            registry,
+        }
+    }
+    /// Reads the Instruments from the file
+    pub fn load(&mut self) -> Result<InstrumentTable> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        let instruments =
+            parse_instruments_with_registry(&fs::read_to_string(&self.path)?, &self.registry)?;
+        self.modified = Some(modified);
+        Ok(instruments)
+    }
+    /// Reads the Instruments from the file if it was modified since it was last loaded, or if it was never loaded
+    pub fn reload_if_changed(&mut self) -> Result<Option<InstrumentTable>> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        if self.modified == Some(modified) {
+            return Ok(None);
+        }
+        Ok(Some(self.load()?))
+    }
+}
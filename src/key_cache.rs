@@ -0,0 +1,164 @@
+use adapters::{buffer_to_pcm, AudioBuffer, SampleAccess};
+use pcm::PCMParameters;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use {Key, Result};
+
+/// Start of every cached key file, the last two characters are the version of the format
+const MAGIC: &[u8; 8] = b"SEQKEY01";
+/// Extension of cached key files
+const EXTENSION: &str = "key";
+/// Version of the crate that generated a cached key, keys of other versions are generated again as generators may have changed
+const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Amount of keys this process started storing, numbers the temporary files so that threads storing the same key do not share one
+static NB_STORES: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps generated keys in a directory, so that later runs load them instead of generating them again.
+/// Only keys of Key Generators giving a cache ID are cached.
+///
+/// A file is named after a hash of everything the key depends on: the version of this crate, the cache ID, the frequency, the PCM parameters and the duration.
+/// All of it is also written at the start of the file and compared when loading, so a hash collision never gives a wrong key.
+#[derive(Clone, Debug)]
+pub struct KeyCache {
+    /// Directory the keys are stored in, created when the first key is stored
+    pub directory: PathBuf,
+}
+
+impl KeyCache {
+    /// Uses a directory as a cache, nothing is read until keys are loaded
+    pub fn new<P: AsRef<Path>>(directory: P) -> KeyCache {
+        KeyCache {
+            directory: directory.as_ref().to_path_buf(),
+        }
+    }
+    /// Loads a key, None if it is not cached or if the file does not hold this exact key
+    /// # Arguments
+    /// * cache_id: Cache ID of the Key Generator
+    /// * frequency: Frequency of the key
+    /// * parameters: PCM parameters the key was generated with
+    /// * duration: Duration the key was generated for
+    pub fn load(
+        &self,
+        cache_id: &str,
+        frequency: f64,
+        parameters: &PCMParameters,
+        duration: f64,
+    ) -> Option<Key> {
+        let header = header(cache_id, frequency, parameters, duration);
+        let data = fs::read(self.path(&header)).ok()?;
+        if !data.starts_with(&header) {
+            return None;
+        }
+        let data = &data[header.len()..];
+        let nb_channels = usize::from(parameters.nb_channels.max(1));
+        if data.len() % (4 * nb_channels) != 0 {
+            return None;
+        }
+        let buffer = AudioBuffer {
+            sample_rate: parameters.sample_rate,
+            nb_channels: parameters.nb_channels,
+            samples: data
+                .chunks(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        };
+        Some(Key {
+            audio: buffer_to_pcm(&buffer),
+            frequency,
+        })
+    }
+    /// Stores a key, creating the directory if needed.
+    /// Keys with loop points, with samples other than Float or with another sample rate or amount of channels than the given parameters are not stored,
+    /// as they would be loaded with the given parameters.
+    /// # Arguments
+    /// * cache_id: Cache ID of the Key Generator
+    /// * parameters: PCM parameters the key was generated with, the same as given to load
+    /// * duration: Duration the key was generated for
+    /// * key: The generated key
+    pub fn store(
+        &self,
+        cache_id: &str,
+        parameters: &PCMParameters,
+        duration: f64,
+        key: &Key,
+    ) -> Result<()> {
+        if key.audio.loop_info.is_some()
+            | (key.audio.parameters.sample_rate != parameters.sample_rate)
+            | (key.audio.parameters.nb_channels != parameters.nb_channels)
+        {
+            return Ok(());
+        }
+        let samples = match key.audio.interleaved_f32() {
+            Ok(s) => s,
+            Err(_) => return Ok(()),
+        };
+        let mut data = header(cache_id, key.frequency, parameters, duration);
+        let path = self.path(&data);
+        data.reserve(samples.len() * 4);
+        for sample in samples {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        fs::create_dir_all(&self.directory)?;
+        // Written next to the final file then renamed, so that another run or thread never reads half a key
+        let temporary = path.with_extension(format!(
+            "{}.{}.{}",
+            EXTENSION,
+            process::id(),
+            NB_STORES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+    /// Removes every cached key. The directory itself and other files in it are left.
+    pub fn clear(&self) -> Result<()> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(e) => e,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new(EXTENSION)) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+    /// Gives the path of the file of a key from its header
+    fn path(&self, header: &[u8]) -> PathBuf {
+        self.directory
+            .join(format!("{:016x}.{}", fnv1a(header), EXTENSION))
+    }
+}
+
+/// Writes everything a key depends on, in a way that does not change between runs or platforms
+fn header(cache_id: &str, frequency: f64, parameters: &PCMParameters, duration: f64) -> Vec<u8> {
+    let mut header =
+        Vec::with_capacity(MAGIC.len() + 16 + CRATE_VERSION.len() + cache_id.len() + 22);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&(CRATE_VERSION.len() as u64).to_le_bytes());
+    header.extend_from_slice(CRATE_VERSION.as_bytes());
+    header.extend_from_slice(&(cache_id.len() as u64).to_le_bytes());
+    header.extend_from_slice(cache_id.as_bytes());
+    header.extend_from_slice(&frequency.to_bits().to_le_bytes());
+    header.extend_from_slice(&parameters.sample_rate.to_le_bytes());
+    header.extend_from_slice(&parameters.nb_channels.to_le_bytes());
+    header.extend_from_slice(&duration.to_bits().to_le_bytes());
+    header
+}
+
+/// 64 bits FNV-1a hash, used instead of the hasher of the standard library as its results can change between Rust versions
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
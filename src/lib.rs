@@ -11,6 +11,27 @@
 //! * Instruments are composed of Keys, each of these have a different pitch.
 //! * A Note is something placed in a Sequence that describes when to make a sound and at which pitch
 //! * A Key is a sound for a particular pitch that an instrument makes.
+//! * Instruments, frequencies and notes are identified by InstrumentId, FrequencyId and NoteId rather than plain numbers,
+//!   so that one cannot be given where another is expected. They convert from and to usize.
+//!
+//! # Sample rates
+//!
+//! Everything time-based (envelopes, releases, latencies, low bit-depth update rates, tempo-synced times, ducking) is defined in seconds or Hertz,
+//! never in samples. Rendering the same project at 22.05, 44.1, 48 or 96 kHz gives the same pitches and timings,
+//! up to the precision of one sample at the output rate. Custom Key Generators and Envelopes should follow the same rule.
+//!
+//! # Features
+//!
+//! * `helper` (default) - The SequenceHelper for importing Sequences from other formats.
+//! * `tone_generators` (default) - Pre-made Key Generators.
+//! * `pcm` (default) - AudioBuffer::from_pcm and AudioBuffer::to_pcm. The pcm crate itself is still always needed.
+//! * `strict-checks` - Checks invariants during render (amplitudes, envelope outputs, frame positions) and returns errors when they break.
+//! * `flac`, `ogg` and `mp3` - Encoders for writing rendered audio to FLAC, Ogg Vorbis and MP3 files. WAV is always available.
+//! * `scripting` - Key Generators and Envelopes written in Rhai scripts loaded at runtime, run sandboxed with limits on operations.
+//! * `instrument_files` - Instruments defined in TOML files, reloaded when they change for tweaking them between renders.
+//! * `arbitrary` - Implements Arbitrary for Note, Sequence, LoopInfo and FrequencyLookupTable for fuzzing.
+//!
+//! Disabling the default features leaves only the core mixer and instrument engine.
 
 // Todo: Implement Panning
 //       Make a trait that replaces the FLUT
@@ -20,10 +41,7 @@
 //       Track volume in helper
 //       Trait for calculating ticks to and from seconds in f64
 //       Multi-threading for render() method
-//       Apply envelope
-//       Implement Pitch changer
 //       Check and fix if necessary each key amplitude passing by the render() method
-//       Check for overflows everywhere
 //       Remove all unimplemented!()
 //       Add errors for all panics!() and everything that should be checked in general
 //       Make the user pass the Pitch changer rather than implying it if None
@@ -31,24 +49,129 @@
 //       Allow for global volume control
 //       Prevent clicking by multiplying last values of each note
 //       New Tone Generators
+//       Portamento between legato notes
+//       Include effect tails in the output duration once effects exist
+//       Velocity mappings for filter cutoff and FM index once these exist
+//       LFOs and delay effects, with rates and times as tempo::SyncedTime
+//       Effects in the Registry once effects exist
+//       Time effects in the RenderProfile once effects exist
+//       Use AudioBuffer for Keys and the mixer, then make the pcm dependency optional behind the pcm feature, which only gates AudioBuffer conversions for now
 
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "flac")]
+extern crate flacenc;
+#[cfg(feature = "mp3")]
+extern crate mp3lame_encoder;
 extern crate pcm;
+#[cfg(feature = "scripting")]
+extern crate rhai;
+#[cfg(feature = "instrument_files")]
+extern crate toml;
+#[cfg(feature = "ogg")]
+extern crate vorbis_rs;
 
+/// Plain float access to the samples of a PCM and audio buffers independent from the pcm crate
+pub mod adapters;
+/// Measurements on rendered audio
+pub mod analysis;
+/// Arbitrary implementations for fuzzing
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls;
+/// Invariant checks enabled by the `strict-checks` feature
+mod checks;
+/// Simulation of sound drivers with a fixed amount of hardware voices
+pub mod driver;
+/// Pre-made Envelopes
+pub mod envelopes;
 /// Contains all errors for this Library
 pub mod error;
+/// Writing rendered audio to files
+pub mod export;
+/// Digital filters
+pub mod filters;
 /// Helps the user to import a Sequence
+#[cfg(feature = "helper")]
 pub mod helper;
+/// Instruments defined in TOML files that can be reloaded on change
+#[cfg(feature = "instrument_files")]
+pub mod instrument_files;
+/// Storing generated keys on disk to reuse them between runs
+pub mod key_cache;
+/// Low bit-depth sample playback emulation
+pub mod low_bit;
+/// Gain trims and equalizers on the output channels of the final mix
+pub mod master;
+/// Comparing rendered instruments to reference recordings
+pub mod matching;
+/// Pitch conversions, interpolation and resampling
+pub mod pitch;
+/// Reverse and speed changes of the playback of Instruments
+pub mod playback;
+/// Names for creating Key Generators and Envelopes from files
+pub mod registry;
+/// Render options and reports
+pub mod render;
+/// Key Generators and Envelopes defined by scripts
+#[cfg(feature = "scripting")]
+pub mod scripting;
+/// Tempo and conversions between beats and seconds
+pub mod tempo;
+/// Plain text representation of Sequences
+pub mod text;
+/// Notes, tempo changes and control changes merged in a single ordered list
+pub mod timeline;
+/// Crossfades between songs and albums of songs played back-to-back
+pub mod transition;
 /// Pre-made Tone Generators representing different Waveforms for use with the sequencer
+#[cfg(feature = "tone_generators")]
 pub mod tone_generators;
+/// Spreading sounds with fewer channels than the project over all of its channels
+pub mod upmix;
+/// Mapping of note velocities to synthesis parameters
+pub mod velocity;
 
+use adapters::AudioBuffer;
+use analysis::{HeadroomWarning, InactiveRegion};
+use driver::{DriverOutput, HardwareDriver};
 use error::SequencerError;
+use export::ExportProfile;
+use key_cache::KeyCache;
+use low_bit::LowBitPlayback;
+use master::ChannelStrip;
 use pcm::{Frame, LoopInfo as PCMLoopInfo, PCMParameters, Sample, PCM};
+use pitch::PitchGlide;
+use playback::PlaybackModifier;
+use render::{
+    OutputSpec, RenderOptions, RenderProfile, RenderReport, SkippedNote, StageTimes, StemStats,
+    Watchdog,
+};
 use std::cmp::max;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+use tempo::{TempoMap, Timecode};
+use upmix::UpmixPolicy;
+use velocity::VelocityMapping;
 
 /// Result type used everywhere in this crate
 type Result<T> = std::result::Result<T, SequencerError>;
 
+/// Longest song that can be rendered, in seconds. Times past this give a TimeOutOfRange error rather than huge buffers or wrapping frame positions.
+pub const MAX_SONG_LENGTH: f64 = 86_400f64;
+
+/// Most octaves a note can be moved by to fit in a KeyRange, so that impossible frequencies do not transpose forever
+const MAX_TRANSPOSE_OCTAVES: u32 = 64;
+
+/// Largest difference in seconds between the duration of a Note and the time between its start and end
+const NOTE_DURATION_TOLERANCE: f64 = 1e-9f64;
+
 /// Makes sure that a value is a usable Time or Frequency
 trait ValidTimeFrequency {
     /// Checks for validity of value for use as a Time or a Frequency
@@ -69,6 +192,26 @@ impl ValidTimeFrequency for f64 {
     }
 }
 
+/// Makes sure a time can be turned into frames: finite, not negative and not past MAX_SONG_LENGTH
+fn check_time(time: f64) -> Result<()> {
+    if !(0f64..=MAX_SONG_LENGTH).contains(&time) {
+        return Err(SequencerError::TimeOutOfRange(time));
+    }
+    Ok(())
+}
+
+/// Converts a position in seconds to a frame, rounded to the nearest frame
+pub fn time_to_frame(time: f64, sample_rate: u32) -> Result<usize> {
+    check_time(time)?;
+    Ok((time * f64::from(sample_rate)).round() as usize)
+}
+
+/// Converts a duration in seconds to an amount of frames, only counting whole frames
+pub fn duration_to_frames(duration: f64, sample_rate: u32) -> Result<usize> {
+    check_time(duration)?;
+    Ok((duration * f64::from(sample_rate)) as usize)
+}
+
 /// The sequencer itself
 pub struct MusicSequencer {
     /// PCM Parameters for controlling the project audio
@@ -79,6 +222,23 @@ pub struct MusicSequencer {
     pub instruments: InstrumentTable,
     /// Table used for storing all possible note frequencies
     pub frequency_lut: FrequencyLookupTable,
+    /// Attenuates lower priority Instruments while higher priority ones are playing. If not set, no ducking is done.
+    pub ducking: Option<Ducking>,
+    /// Pre-rendered sound of frozen Instruments by ID, mixed in instead of playing their notes. See freeze_instruments.
+    pub frozen: HashMap<InstrumentId, Vec<Frame>>,
+    /// Directory where keys are stored and loaded from, so that later runs do not generate them again. If not set, keys are not cached.
+    pub key_cache: Option<KeyCache>,
+    /// Trim and equalizer of each output channel by channel index, applied on the final mix. Channels without a strip are left untouched.
+    pub channel_strips: Vec<ChannelStrip>,
+    /// How the sound of Instruments with fewer channels than the project, like stereo samples in a surround project, is spread over its channels
+    pub upmix: UpmixPolicy,
+}
+
+/// Mix rule lowering the volume of Instruments while an Instrument with a higher priority plays, like channel priority in old sound drivers
+#[derive(Clone)]
+pub struct Ducking {
+    /// Gain applied on lower priority Instruments, 0 mutes them and 1 leaves them untouched
+    pub gain: f64,
 }
 
 /// Contains notes to play in a sequence
@@ -100,20 +260,88 @@ pub struct Note {
     /// How long this note plays for
     pub duration: f64,
     /// The height for this note, key for the Frequency Lookup Table
-    pub frequency_id: usize,
+    pub frequency_id: FrequencyId,
     /// Velocity of the key being pressed down
     pub on_velocity: f64,
     /// Velocity when releasing the key
     pub off_velocity: f64,
     /// Instrument to use for this note
-    pub instrument_id: usize,
+    pub instrument_id: InstrumentId,
+    /// Offset in cents from the frequency of the Frequency ID, 0 plays the frequency as it is
+    pub detune: f64,
+}
+
+/// Identifies an Instrument in an InstrumentTable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InstrumentId(pub usize);
+
+/// Identifies a frequency in a FrequencyLookupTable, and the Key of an Instrument playing it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FrequencyId(pub usize);
+
+/// Identifies a note by its position in the notes of a Sequence
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NoteId(pub usize);
+
+impl From<usize> for InstrumentId {
+    fn from(id: usize) -> InstrumentId {
+        InstrumentId(id)
+    }
+}
+
+impl From<InstrumentId> for usize {
+    fn from(id: InstrumentId) -> usize {
+        id.0
+    }
+}
+
+impl fmt::Display for InstrumentId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<usize> for FrequencyId {
+    fn from(id: usize) -> FrequencyId {
+        FrequencyId(id)
+    }
+}
+
+impl From<FrequencyId> for usize {
+    fn from(id: FrequencyId) -> usize {
+        id.0
+    }
+}
+
+impl fmt::Display for FrequencyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<usize> for NoteId {
+    fn from(id: usize) -> NoteId {
+        NoteId(id)
+    }
+}
+
+impl From<NoteId> for usize {
+    fn from(id: NoteId) -> usize {
+        id.0
+    }
+}
+
+impl fmt::Display for NoteId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
 /// Used to provide indexes for float values, along with error checking and easy conversion between different formats
 #[derive(Clone, Default)]
 pub struct FrequencyLookupTable {
     /// HashMap used to get a frequency from a float
-    pub lut: HashMap<usize, f64>,
+    pub lut: HashMap<FrequencyId, f64>,
 }
 
 /// Represents where a loop starts and ends
@@ -128,19 +356,113 @@ pub struct LoopInfo {
 /// List of instruments used by the sequencer
 pub struct InstrumentTable {
     /// Instruments contained in the list
-    pub instruments: HashMap<usize, Instrument>,
+    pub instruments: HashMap<InstrumentId, Instrument>,
 }
 
 /// Defines how a note being played should sound
 pub struct Instrument {
     /// Keys of the instrument
-    pub keys: HashMap<usize, Key>,
+    pub keys: HashMap<FrequencyId, Key>,
     /// The Key Generator for generating every needed key. If not specified, push at least one key to 'keys' for the pitch change.
     pub key_generator: Option<Box<KeyGenerator>>,
     /// Is this instrument loopable ? If there is an envelope, this should be set to true.
     pub loopable: bool,
     /// Envelope for the instrument. If not set, the Instrument will play at max loudness all the time.
     pub envelope: Option<Box<Envelope>>,
+    /// Spreads notes played at the same time across the stereo field. Only used when rendering with 2 channels.
+    pub auto_pan: Option<AutoPan>,
+    /// Priority of this instrument when ducking, higher priority instruments attenuate lower priority ones.
+    pub priority: u8,
+    /// Quantizes the sound of this instrument to emulate low bit-depth sample channels. If not set, the sound is left as is.
+    pub low_bit: Option<LowBitPlayback>,
+    /// Does the envelope restart on notes starting before the previous one ended (legato) ? If not, it continues from the first note.
+    pub legato_retrigger: bool,
+    /// Frequencies this instrument can play, for splitting the keyboard between instruments. If not set, every frequency is played.
+    pub key_range: Option<KeyRange>,
+    /// Instruments played instead of this one for each note. If empty, this instrument plays its notes itself.
+    /// Layers of the Instruments used as layers are not played.
+    pub layers: Vec<InstrumentLayer>,
+    /// Maps the on velocity of notes to their amplitude. If not set, the velocity is used as the amplitude.
+    pub velocity_to_amplitude: Option<VelocityMapping>,
+    /// Plays the keys of this instrument reversed or at another speed. If not set, keys are played as they are.
+    /// Keys of a Key Generator are modified once when generated, other keys each time a note is played.
+    pub playback: Option<PlaybackModifier>,
+}
+
+/// An Instrument played as part of a layered Instrument
+#[derive(Clone)]
+pub struct InstrumentLayer {
+    /// ID of the Instrument playing this layer
+    pub instrument_id: InstrumentId,
+    /// Gain applied on this layer
+    pub gain: f64,
+    /// Pan position of this layer from -1 (full left) to 1 (full right). If not set, the AutoPan of the layer Instrument is used.
+    pub pan: Option<f64>,
+    /// Transposition of this layer in cents
+    pub transpose: f64,
+    /// Lowest and highest on velocities, included, for which this layer plays. If not set, it plays at any velocity.
+    pub velocity_range: Option<(f64, f64)>,
+    /// Maps the on velocity of notes to a gain multiplied with the layer gain, for crossfading layers with velocity
+    pub velocity_to_gain: Option<VelocityMapping>,
+}
+
+/// Gain and pan given to a note by the layer it comes from
+#[derive(Clone)]
+struct LayerMix {
+    /// Gain of the layer
+    gain: f64,
+    /// Pan of the layer, overrides the AutoPan
+    pan: Option<f64>,
+}
+
+/// Lowest and highest frequencies an Instrument can play
+#[derive(Clone)]
+pub struct KeyRange {
+    /// Lowest frequency of the range in Hertz, included
+    pub low: f64,
+    /// Highest frequency of the range in Hertz, included
+    pub high: f64,
+    /// What happens to notes outside of the range
+    pub out_of_range: OutOfRangePolicy,
+}
+
+impl KeyRange {
+    /// Gives an error unless both ends are finite and 0 < low <= high
+    pub fn check(&self) -> Result<()> {
+        let valid = self.low.is_finite()
+            & self.high.is_finite()
+            & (0f64 < self.low)
+            & (self.low <= self.high);
+        if !valid {
+            return Err(SequencerError::InvalidParameter(format!(
+                "key range from {} to {} should have 0 < low <= high",
+                self.low, self.high
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Defines what an Instrument does with notes outside of its KeyRange
+#[derive(Clone, Copy)]
+pub enum OutOfRangePolicy {
+    /// Moves the note by octaves until it is in range. If the range is narrower than an octave, the note goes to the closest octave.
+    Transpose,
+    /// Does not play the note
+    Drop,
+    /// Returns an error
+    Error,
+}
+
+/// Defines how an Instrument automatically places its notes in the stereo field.
+/// Pan positions go from -1 (full left) to 1 (full right).
+#[derive(Clone)]
+pub enum AutoPan {
+    /// Notes playing at the same time are spread by pitch, lowest on the left and highest on the right.
+    /// The value is the width of the spread, between 0 and 1.
+    PitchSpread(f64),
+    /// Each new note takes the next pan position in the list, going back to the start once the end is reached.
+    RoundRobin(Vec<f64>),
 }
 
 /// Sound for a particular frequency made by an instrument
@@ -161,13 +483,28 @@ pub trait KeyGenerator {
     /// * duration - The longest time this key will be held for.
     /// This is useful if the generator needs to know how long it needs to run to create a good sound.
     /// Can be completely ignored.
+    ///
+    /// Keys should be generated from the time in seconds rather than from sample counts, so that they sound the same at every sample rate.
     fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key;
+    /// Delay in seconds before the sound of the generated keys actually starts, for example from a lookahead.
+    /// The mixer skips this much of every key so that all Instruments stay aligned.
+    fn latency(&self) -> f64 {
+        0f64
+    }
+    /// Identifies the generator along with all of its settings, for storing its keys in a KeyCache.
+    /// Two generators giving the same ID have to generate the same keys. If None, keys are never cached.
+    fn cache_id(&self) -> Option<String> {
+        None
+    }
 }
 
 /// Changes the pitch of an already existing key for crating the others, fallback if there is nothing else to use.
+/// Keys keep the channels of the original key, they are spread over the channels of the project when mixed.
 pub struct KeyPitchChanger {
     /// The Original key to use for pitch change
     pub original_key: Key,
+    /// Bend at the start of every key, None for keys staying at their pitch
+    pub glide: Option<PitchGlide>,
 }
 
 /// Defines how the loudness for an instrument behaves with time
@@ -179,38 +516,520 @@ pub trait Envelope {
     /// Output - Amplitude for given time, should be between 0 and 1 included.
     fn before_during_sustain(&self, time: &f64) -> f64;
     /// Defines behavior after sustain in the same manner as before and during sustain.
+    /// Time is counted from the moment the key is released.
     fn after_sustain(&self, time: &f64) -> f64;
+    /// Same as after_sustain, for a key released at any level, like during the attack or the decay.
+    /// By default, after_sustain is scaled so that it starts from the level, it is used as it is if it starts from silence.
+    fn release_from(&self, time: &f64, level: f64) -> f64 {
+        let start = self.after_sustain(&0f64);
+        if start > 0f64 {
+            self.after_sustain(time) * level / start
+        } else {
+            self.after_sustain(time)
+        }
+    }
+    /// How long the sound keeps going after the key is released, in seconds. Nothing is played past this time.
+    fn release_duration(&self) -> f64 {
+        0f64
+    }
 }
 
 impl MusicSequencer {
+    /// Creates a new MusicSequencer with no ducking, no frozen Instruments and no key cache
+    pub fn new(
+        pcm_parameters: PCMParameters,
+        sequence: Sequence,
+        instruments: InstrumentTable,
+        frequency_lut: FrequencyLookupTable,
+    ) -> MusicSequencer {
+        MusicSequencer {
+            pcm_parameters,
+            sequence,
+            instruments,
+            frequency_lut,
+            ducking: None,
+            frozen: HashMap::new(),
+            key_cache: None,
+            channel_strips: Vec::new(),
+            upmix: UpmixPolicy::default(),
+        }
+    }
+    /// Renders the sound of Instruments once and keeps it, so that the next renders mix it in instead of generating it again.
+    /// Frozen Instruments still count in the mix, so the other Instruments sound the same as before.
+    /// The sound is not updated when the Sequence or the Instruments change, unfreeze them for that.
+    /// Layered Instruments are frozen through the Instruments of their layers.
+    pub fn freeze_instruments(&mut self, instrument_ids: &[InstrumentId]) -> Result<()> {
+        let mut stems = HashMap::new();
+        self.render_stems(Some(&mut stems), None, None)?;
+        for instrument_id in instrument_ids {
+            self.instruments.get(instrument_id)?;
+            let stem = stems.remove(instrument_id).unwrap_or_default();
+            self.frozen.insert(*instrument_id, stem);
+        }
+        Ok(())
+    }
+    /// Removes the pre-rendered sound of an Instrument, its notes are played again in the next renders
+    pub fn unfreeze_instrument(&mut self, instrument_id: InstrumentId) {
+        self.frozen.remove(&instrument_id);
+    }
     /// Runs everything and gives the final PCM
     pub fn render(&mut self) -> Result<PCM> {
-        self.gen_instrument_keys()?;
+        self.render_stems(None, None, None)
+    }
+    /// Renders, also mixing the sound of each Instrument on its own in the stems if given,
+    /// adding the time spent by each Instrument to the times if given and stopping when going past the limits of the watchdog if given
+    fn render_stems(
+        &mut self,
+        stems: Option<&mut HashMap<InstrumentId, Vec<Frame>>>,
+        times: Option<&mut HashMap<InstrumentId, StageTimes>>,
+        watchdog: Option<&Watchdog>,
+    ) -> Result<PCM> {
+        let (sequence, frequency_lut, layer_mixes) = self.expand_layers()?;
+        let original_sequence = mem::replace(&mut self.sequence, sequence);
+        let original_frequency_lut = mem::replace(&mut self.frequency_lut, frequency_lut);
+        let result = self.render_notes(&layer_mixes, stems, times, watchdog);
+        self.sequence = original_sequence;
+        self.frequency_lut = original_frequency_lut;
+        result
+    }
+    /// Gives the exact amount of frames, channels and duration a render will have along with an estimate of its memory, without rendering anything.
+    /// Debug beeps of render_with_options can make the output longer when notes past the end cannot be played.
+    pub fn output_spec(&mut self) -> Result<OutputSpec> {
+        let (sequence, _, _) = self.expand_layers()?;
+        let original_sequence = mem::replace(&mut self.sequence, sequence);
+        let duration = self.calc_output_duration();
+        self.sequence = original_sequence;
+        OutputSpec::new(
+            self.pcm_parameters.nb_channels,
+            self.pcm_parameters.sample_rate,
+            duration?,
+        )
+    }
+    /// Renders, then calls a function for every bar and beat boundary of the rendered audio in order,
+    /// so that video or visualization pipelines can sync to the exact frames of the song.
+    pub fn render_with_timecodes<F: FnMut(&Timecode)>(
+        &mut self,
+        tempo_map: &TempoMap,
+        mut on_timecode: F,
+    ) -> Result<PCM> {
+        let pcm = self.render()?;
+        let duration = pcm.frames.len() as f64 / f64::from(self.pcm_parameters.sample_rate);
+        for timecode in tempo_map.timecodes(self.pcm_parameters.sample_rate, duration)? {
+            on_timecode(&timecode);
+        }
+        Ok(pcm)
+    }
+    /// Renders the Sequence with other Instruments, to compare instrument sets without creating another MusicSequencer.
+    /// The Instruments of the sequencer are left untouched, keys generated for the given ones stay in them for the next render.
+    pub fn render_with_instruments(&mut self, instruments: &mut InstrumentTable) -> Result<PCM> {
+        mem::swap(&mut self.instruments, instruments);
+        let result = self.render();
+        mem::swap(&mut self.instruments, instruments);
+        result
+    }
+    /// Renders with options, and gives a report of what happened along with the PCM.
+    /// If a limit of the options is exceeded, the error holds the report of what was done until then.
+    pub fn render_with_options(&mut self, options: &RenderOptions) -> Result<(PCM, RenderReport)> {
+        let watchdog = Watchdog::new(options.limits);
+        let mut report = RenderReport::default();
+        let mut stems = HashMap::new();
+        let mut times = HashMap::new();
+        self.sequence.sort_by_time();
+        let mut playable = Sequence {
+            notes: Vec::with_capacity(self.sequence.notes.len()),
+            loop_info: self.sequence.loop_info.clone(),
+        };
+        let notes = self.sequence.notes.clone();
+        for note in notes {
+            if !options.debug_beeps {
+                playable.add_note(note);
+                continue;
+            }
+            match self.check_note(&note) {
+                Ok(()) => playable.add_note(note),
+                Err(e) => report.skipped_notes.push(SkippedNote {
+                    start_at: note.start_at,
+                    end_at: note.end_at,
+                    instrument_id: note.instrument_id,
+                    frequency_id: note.frequency_id,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        let original_sequence = mem::replace(&mut self.sequence, playable);
+        let result = self.render_stems(
+            if options.stem_stats {
+                Some(&mut stems)
+            } else {
+                None
+            },
+            if options.profile {
+                Some(&mut times)
+            } else {
+                None
+            },
+            Some(&watchdog),
+        );
+        self.sequence = original_sequence;
+        if options.profile {
+            report.profile = Some(RenderProfile::from_times(times));
+        }
+        let mut pcm = match result {
+            Ok(p) => p,
+            Err(SequencerError::RenderLimitExceeded(limit, _)) => {
+                return Err(SequencerError::RenderLimitExceeded(limit, Box::new(report)))
+            }
+            Err(e) => return Err(e),
+        };
+        let mut instrument_ids: Vec<InstrumentId> = stems.keys().cloned().collect();
+        instrument_ids.sort();
+        for instrument_id in instrument_ids {
+            let stem = PCM {
+                parameters: pcm.parameters.clone(),
+                loop_info: None,
+                frames: stems.remove(&instrument_id).unwrap_or_default(),
+            };
+            report.stems.push(StemStats::measure(instrument_id, &stem)?);
+        }
+        let nb_channels = self.pcm_parameters.nb_channels as usize;
+        let sample_rate = self.pcm_parameters.sample_rate;
+        for skipped in &report.skipped_notes {
+            // Notes with an impossible start beep at the start of the song
+            let start = time_to_frame(skipped.start_at, sample_rate).unwrap_or(0);
+            let beep = render::beep(sample_rate, nb_channels, skipped.end_at - skipped.start_at);
+            if pcm.frames.len() < start + beep.len() {
+                pcm.frames.resize(
+                    start + beep.len(),
+                    Frame {
+                        samples: vec![Sample::Float(0f32); nb_channels],
+                    },
+                );
+            }
+            for (out, frame) in pcm.frames[start..].iter_mut().zip(beep) {
+                for (s1, s2) in out.samples.iter_mut().zip(frame.samples) {
+                    if let (Sample::Float(a), Sample::Float(b)) = (&*s1, s2) {
+                        *s1 = Sample::Float(a + b);
+                    }
+                }
+            }
+        }
+        Ok((pcm, report))
+    }
+    /// Makes sure a note can be played: its times are in range, it lasts, its frequency and Instrument exist, and it is accepted by every Instrument playing it
+    fn check_note(&mut self, note: &Note) -> Result<()> {
+        check_time(note.start_at)?;
+        check_time(note.end_at)?;
+        note.duration.check_valid_time_frequency()?;
+        if note.end_at <= note.start_at {
+            return Err(SequencerError::TimeOutOfRange(note.end_at));
+        }
+        let frequency = *self.frequency_lut.get(&note.frequency_id)?;
+        let layers = self.instruments.get(&note.instrument_id)?.layers.clone();
+        let frequency = pitch::shift_by_cents(frequency, note.detune);
+        let played: Vec<(InstrumentId, f64)> = if layers.is_empty() {
+            vec![(note.instrument_id, frequency)]
+        } else {
+            layers
+                .iter()
+                .map(|l| (l.instrument_id, pitch::shift_by_cents(frequency, l.transpose)))
+                .collect()
+        };
+        for (instrument_id, frequency) in played {
+            let instrument = self.instruments.get(&instrument_id)?;
+            if instrument.key_generator.is_none() {
+                instrument.get_any_key()?;
+            }
+            instrument.resolve_frequency(frequency)?;
+        }
+        Ok(())
+    }
+    /// Quickly finds the regions that will likely clip at the current gains without rendering anything.
+    /// Every key is assumed to reach full amplitude, and notes are counted until the end of their release.
+    pub fn scan_headroom(&mut self) -> Result<Vec<HeadroomWarning>> {
+        let (mut sequence, _, layer_mixes) = self.expand_layers()?;
+        let amplitude_per_note = f64::from(sequence.calc_max_notes_at_once() as u16).recip();
+        let mut notes = Vec::with_capacity(sequence.notes.len());
+        for (note, layer_mix) in sequence.notes.iter().zip(layer_mixes) {
+            let instrument = self.instruments.get(&note.instrument_id)?;
+            notes.push((
+                note.start_at,
+                note.end_at + instrument.release_duration(),
+                amplitude_per_note
+                    * instrument.velocity_amplitude(note.on_velocity).abs()
+                    * layer_mix.gain.abs(),
+            ));
+        }
+        Ok(analysis::find_clipping_regions(&notes))
+    }
+    /// Finds, for each Instrument playing notes, the regions where it makes no sound for at least a minimum length in seconds.
+    /// Notes count until the end of their release. Layered Instruments are looked at through the Instruments of their layers.
+    /// The regions are only reported: rendering mixes each note on its own, so it already does no work for an Instrument where it plays nothing.
+    /// Gives an error if the minimum length is not strictly positive.
+    pub fn find_inactive_regions(
+        &mut self,
+        min_length: f64,
+    ) -> Result<HashMap<InstrumentId, Vec<InactiveRegion>>> {
+        let (sequence, _, _) = self.expand_layers()?;
+        let mut active: HashMap<InstrumentId, Vec<(f64, f64)>> = HashMap::new();
+        let mut end = 0f64;
+        for note in &sequence.notes {
+            let stop = note.end_at + self.instruments.get(&note.instrument_id)?.release_duration();
+            end = end.max(stop);
+            active
+                .entry(note.instrument_id)
+                .or_default()
+                .push((note.start_at, stop));
+        }
+        active
+            .into_iter()
+            .map(|(id, a)| Ok((id, analysis::find_inactive_regions(&a, end, min_length)?)))
+            .collect()
+    }
+    /// Plays the Sequence through a simulated HardwareDriver and renders what it would sound like,
+    /// with the Envelopes of Instruments going through the volume register of the voices.
+    /// Also gives what the driver did with each note.
+    pub fn render_with_driver(&mut self, driver: &HardwareDriver) -> Result<(PCM, DriverOutput)> {
+        let output = driver.simulate(&self.sequence, &mut self.instruments, &self.frequency_lut)?;
+        let original_sequence = mem::replace(&mut self.sequence, output.sequence.clone());
+        let mut envelopes = Vec::new();
+        for (instrument_id, instrument) in &mut self.instruments.instruments {
+            if let Some(e) = instrument.envelope.take() {
+                let shared = Rc::new(e);
+                instrument.envelope = Some(Box::new(driver.register_envelope(shared.clone())));
+                envelopes.push((*instrument_id, shared));
+            }
+        }
+        let result = self.render();
+        for (instrument_id, shared) in envelopes {
+            if let Some(instrument) = self.instruments.instruments.get_mut(&instrument_id) {
+                // Dropping the RegisterEnvelope first leaves the original Envelope as the only owner
+                instrument.envelope = None;
+                instrument.envelope = Rc::try_unwrap(shared).ok();
+            }
+        }
+        self.sequence = original_sequence;
+        Ok((result?, output))
+    }
+    /// Renders and gives the result as plain interleaved floats, for using it without the pcm crate
+    pub fn render_buffer(&mut self) -> Result<AudioBuffer> {
+        adapters::pcm_to_buffer(&self.render()?)
+    }
+    /// Renders and writes the result to a file, the format is picked from the extension (wav, flac, ogg or mp3)
+    pub fn render_to_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        export::write_file(&self.render()?, path)
+    }
+    /// Renders and writes the result to a file, along with a text file of the frame of every bar and beat of a TempoMap.
+    /// See TempoMap::write_beat_grid for the format of the beat grid.
+    pub fn render_to_file_with_beat_grid<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        path: P,
+        tempo_map: &TempoMap,
+        beat_grid_path: Q,
+    ) -> Result<()> {
+        let pcm = self.render()?;
+        export::write_file(&pcm, path)?;
+        let duration = pcm.frames.len() as f64 / f64::from(self.pcm_parameters.sample_rate);
+        let mut writer = BufWriter::new(File::create(beat_grid_path)?);
+        tempo_map.write_beat_grid(self.pcm_parameters.sample_rate, duration, &mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+    /// Renders and writes the result to a file following an export profile picked by name, see ExportProfile::by_name
+    pub fn render_with_profile<P: AsRef<Path>>(&mut self, profile: &str, path: P) -> Result<()> {
+        ExportProfile::by_name(profile)?.write_file(&self.render()?, path)
+    }
+    /// Replaces the Instruments by the ones of an instrument file if it changed since it was last loaded.
+    /// Gives whether the Instruments were replaced. If the file cannot be read, the current Instruments are kept.
+    #[cfg(feature = "instrument_files")]
+    pub fn reload_instruments(
+        &mut self,
+        file: &mut instrument_files::InstrumentFile,
+    ) -> Result<bool> {
+        match file.reload_if_changed()? {
+            Some(instruments) => {
+                self.instruments = instruments;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    /// Replaces notes of layered Instruments by notes for each of their layers, and moves the detune of notes into their frequency.
+    /// Gives the new Sequence sorted by time, a FrequencyLookupTable containing the transposed and detuned frequencies and the mix of each note.
+    fn expand_layers(&mut self) -> Result<(Sequence, FrequencyLookupTable, Vec<LayerMix>)> {
+        self.sequence.sort_by_time();
+        let mut sequence = Sequence {
+            notes: Vec::with_capacity(self.sequence.notes.len()),
+            loop_info: self.sequence.loop_info.clone(),
+        };
+        let mut frequency_lut = self.frequency_lut.clone();
+        let mut transposed_ids: HashMap<(FrequencyId, u64), FrequencyId> = HashMap::new();
+        let mut next_frequency_id = match self.frequency_lut.lut.keys().max() {
+            Some(id) => id.0 + 1,
+            None => 0,
+        };
+        let mut mixes = Vec::with_capacity(self.sequence.notes.len());
+        for note in &self.sequence.notes {
+            let layers = self.instruments.get(&note.instrument_id)?.layers.clone();
+            let mut played = Vec::with_capacity(layers.len().max(1));
+            if layers.is_empty() {
+                let mix = LayerMix {
+                    gain: 1f64,
+                    pan: None,
+                };
+                played.push((note.instrument_id, 0f64, mix));
+            }
+            for layer in layers {
+                self.instruments.get(&layer.instrument_id)?;
+                if let Some((low, high)) = layer.velocity_range {
+                    if (note.on_velocity < low) | (note.on_velocity > high) {
+                        continue;
+                    }
+                }
+                let mix = LayerMix {
+                    gain: match layer.velocity_to_gain {
+                        Some(ref m) => layer.gain * m.map(note.on_velocity),
+                        None => layer.gain,
+                    },
+                    pan: layer.pan,
+                };
+                played.push((layer.instrument_id, layer.transpose, mix));
+            }
+            for (instrument_id, transpose, mix) in played {
+                let cents = transpose + note.detune;
+                let frequency_id = if cents == 0f64 {
+                    note.frequency_id
+                } else {
+                    let frequency = *self.frequency_lut.get(&note.frequency_id)?;
+                    *transposed_ids
+                        .entry((note.frequency_id, cents.to_bits()))
+                        .or_insert_with(|| {
+                            frequency_lut.lut.insert(
+                                FrequencyId(next_frequency_id),
+                                pitch::shift_by_cents(frequency, cents),
+                            );
+                            next_frequency_id += 1;
+                            FrequencyId(next_frequency_id - 1)
+                        })
+                };
+                sequence.add_note(Note {
+                    frequency_id,
+                    instrument_id,
+                    detune: 0f64,
+                    ..note.clone()
+                });
+                mixes.push(mix);
+            }
+        }
+        Ok((sequence, frequency_lut, mixes))
+    }
+    /// Renders the notes of the Sequence, each with the mix of its layer.
+    /// If stems are given, the sound of each Instrument is also mixed on its own in them.
+    /// If times are given, the time spent by each Instrument is added to them.
+    /// If a watchdog is given, the render stops when going past its limits.
+    fn render_notes(
+        &mut self,
+        layer_mixes: &[LayerMix],
+        mut stems: Option<&mut HashMap<InstrumentId, Vec<Frame>>>,
+        mut times: Option<&mut HashMap<InstrumentId, StageTimes>>,
+        watchdog: Option<&Watchdog>,
+    ) -> Result<PCM> {
+        // The output size is checked first, so that a Sequence too long to render does not generate keys for nothing
+        let duration = self.calc_output_duration()?;
+        let nb_frames = duration_to_frames(duration, self.pcm_parameters.sample_rate)?;
+        if let Some(w) = watchdog {
+            w.check_frames(nb_frames)?;
+        }
+        self.gen_keys_timed(times.as_deref_mut(), watchdog)?;
         let max_notes_at_once = self.sequence.calc_max_notes_at_once();
         let amplitude_per_note = f32::from(max_notes_at_once as u16).recip();
-        let duration = self.sequence.calc_music_duration();
-        let nb_frames = (duration * f64::from(self.pcm_parameters.sample_rate)) as usize;
         let mut out_pcm_data = vec![
             Frame {
                 samples: vec![Sample::Float(0f32); self.pcm_parameters.nb_channels as usize],
             };
             nb_frames
         ];
-        for note in &self.sequence.notes {
-            let to_add = self.instruments
-                .get(&note.instrument_id)?
-                .gen_sound(&note.frequency_id, &note.duration)?;
+        let note_pans = self.calc_note_pans()?;
+        let envelope_offsets = self.calc_envelope_offsets()?;
+        let priorities = match self.ducking {
+            Some(_) => Some(self.calc_priorities(nb_frames)?),
+            None => None,
+        };
+        let mut previous_note_start = None;
+        for (((note, pan), envelope_offset), layer_mix) in self.sequence
+            .notes
+            .iter()
+            .zip(note_pans)
+            .zip(envelope_offsets)
+            .zip(layer_mixes)
+        {
+            if let Some(w) = watchdog {
+                w.check_time()?;
+            }
+            if self.frozen.contains_key(&note.instrument_id) {
+                continue;
+            }
+            let channel_gains = pan_gains(
+                layer_mix.pan.or(pan),
+                self.pcm_parameters.nb_channels as usize,
+            );
+            let layer_gain = layer_mix.gain as f32;
+            let instrument = self.instruments.get(&note.instrument_id)?;
+            let velocity_gain = instrument.velocity_amplitude(note.on_velocity) as f32;
+            if instrument
+                .resolve_frequency(*self.frequency_lut.get(&note.frequency_id)?)?
+                .is_none()
+            {
+                continue;
+            }
+            let clock = times.as_ref().map(|_| Instant::now());
+            let to_add =
+                instrument.gen_sound(&note.frequency_id, &note.duration, &envelope_offset)?;
+            let to_add = self.upmix.upmix_frames(to_add.frames, channel_gains.len());
+            if let (Some(t), Some(c)) = (times.as_mut(), clock) {
+                t.entry(note.instrument_id).or_default().synthesis += c.elapsed();
+            }
+            let clock = times.as_ref().map(|_| Instant::now());
             let mut frame_id = 0usize;
-            let mut frame_id_out =
-                (note.start_at * f64::from(self.pcm_parameters.sample_rate)).round() as usize;
-            while frame_id < to_add.frames.len() {
-                for sample_id in 0..self.pcm_parameters.nb_channels as usize {
+            let mut frame_id_out = time_to_frame(note.start_at, self.pcm_parameters.sample_rate)?;
+            checks::check_frame_position(previous_note_start, frame_id_out)?;
+            previous_note_start = Some(frame_id_out);
+            let mut stem = stems.as_mut().map(|s| {
+                s.entry(note.instrument_id).or_insert_with(|| {
+                    vec![
+                        Frame {
+                            samples: vec![Sample::Float(0f32); channel_gains.len()],
+                        };
+                        nb_frames
+                    ]
+                })
+            });
+            while (frame_id < to_add.len()) & (frame_id_out < nb_frames) {
+                let ducking_gain = match (&self.ducking, &priorities) {
+                    (Some(d), Some(p)) => match p.get(frame_id_out) {
+                        Some(max_priority) if *max_priority > instrument.priority => d.gain as f32,
+                        _ => 1f32,
+                    },
+                    _ => 1f32,
+                };
+                for (sample_id, gain) in channel_gains.iter().enumerate() {
                     match out_pcm_data[frame_id_out].samples[sample_id] {
-                        Sample::Float(s1) => match to_add.frames[frame_id].samples[sample_id] {
+                        Sample::Float(s1) => match to_add[frame_id].samples[sample_id] {
                             Sample::Float(s2) => {
-                                out_pcm_data[frame_id_out].samples[sample_id] = Sample::Float(
-                                    s1 + (s2 * amplitude_per_note * (note.on_velocity as f32)),
-                                )
+                                let mixed = s2
+                                    * amplitude_per_note
+                                    * velocity_gain
+                                    * gain
+                                    * layer_gain
+                                    * ducking_gain;
+                                out_pcm_data[frame_id_out].samples[sample_id] =
+                                    Sample::Float(s1 + mixed);
+                                if let Some(ref mut stem) = stem {
+                                    if let Sample::Float(s) = stem[frame_id_out].samples[sample_id]
+                                    {
+                                        stem[frame_id_out].samples[sample_id] =
+                                            Sample::Float(s + mixed);
+                                    }
+                                }
                             }
                             _ => unimplemented!(),
                         },
@@ -220,6 +1039,37 @@ impl MusicSequencer {
                 frame_id += 1;
                 frame_id_out += 1;
             }
+            if let (Some(t), Some(c)) = (times.as_mut(), clock) {
+                t.entry(note.instrument_id).or_default().mixing += c.elapsed();
+            }
+        }
+        for (instrument_id, frozen) in &self.frozen {
+            let clock = times.as_ref().map(|_| Instant::now());
+            for (out, frame) in out_pcm_data.iter_mut().zip(frozen) {
+                for (s1, s2) in out.samples.iter_mut().zip(&frame.samples) {
+                    if let (Sample::Float(a), Sample::Float(b)) = (&*s1, s2) {
+                        *s1 = Sample::Float(a + b);
+                    }
+                }
+            }
+            if let Some(ref mut s) = stems {
+                s.insert(*instrument_id, frozen.clone());
+            }
+            if let (Some(t), Some(c)) = (times.as_mut(), clock) {
+                t.entry(*instrument_id).or_default().mixing += c.elapsed();
+            }
+        }
+        master::apply_channel_strips(
+            &self.channel_strips,
+            &mut out_pcm_data,
+            self.pcm_parameters.sample_rate,
+        );
+        for frame in &out_pcm_data {
+            for sample in &frame.samples {
+                if let Sample::Float(s) = sample {
+                    checks::check_amplitude(*s)?;
+                }
+            }
         }
         Ok(PCM {
             parameters: PCMParameters {
@@ -231,11 +1081,127 @@ impl MusicSequencer {
             frames: out_pcm_data,
         })
     }
-    /// Generates all frequencies needed for processing
+    /// Calculates the pan position of every note in the sequence from the AutoPan setting of its Instrument.
+    /// The Sequence needs to be sorted by time beforehand.
+    pub fn calc_note_pans(&mut self) -> Result<Vec<Option<f64>>> {
+        let mut pans = Vec::with_capacity(self.sequence.notes.len());
+        let mut round_robin_positions: HashMap<InstrumentId, usize> = HashMap::new();
+        for (note_id, note) in self.sequence.notes.iter().enumerate() {
+            let pan = match self.instruments.get(&note.instrument_id)?.auto_pan {
+                Some(AutoPan::PitchSpread(width)) => {
+                    let frequency = self.frequency_lut.get(&note.frequency_id)?;
+                    let mut nb_lower = 0usize;
+                    let mut nb_at_once = 0usize;
+                    for (other_id, other) in self.sequence.notes.iter().enumerate() {
+                        if other.start_at >= note.end_at {
+                            break;
+                        }
+                        if (other.instrument_id != note.instrument_id)
+                            | (other.end_at <= note.start_at)
+                        {
+                            continue;
+                        }
+                        let other_frequency = self.frequency_lut.get(&other.frequency_id)?;
+                        if (other_frequency < frequency)
+                            | ((other_frequency == frequency) & (other_id < note_id))
+                        {
+                            nb_lower += 1;
+                        }
+                        nb_at_once += 1;
+                    }
+                    if nb_at_once > 1 {
+                        Some(width * ((2 * nb_lower) as f64 / (nb_at_once - 1) as f64 - 1f64))
+                    } else {
+                        Some(0f64)
+                    }
+                }
+                Some(AutoPan::RoundRobin(ref positions)) => {
+                    let position = round_robin_positions
+                        .entry(note.instrument_id)
+                        .or_insert(0);
+                    let pan = if positions.is_empty() {
+                        0f64
+                    } else {
+                        positions[*position % positions.len()]
+                    };
+                    *position += 1;
+                    Some(pan)
+                }
+                None => None,
+            };
+            pans.push(pan);
+        }
+        Ok(pans)
+    }
+    /// Calculates how long the rendered audio will be, from the end of the last note including the release of its instrument
+    pub fn calc_output_duration(&mut self) -> Result<f64> {
+        let mut duration = 0f64;
+        for note in &self.sequence.notes {
+            let end = note.end_at + self.instruments.get(&note.instrument_id)?.release_duration();
+            if end > duration {
+                duration = end
+            }
+        }
+        Ok(duration)
+    }
+    /// Calculates for every note the time at which its envelope starts. This is always 0 unless the instrument does not retrigger on legato,
+    /// in which case notes overlapping the previous ones continue the envelope from the start of the first one.
+    /// The Sequence needs to be sorted by time beforehand.
+    pub fn calc_envelope_offsets(&mut self) -> Result<Vec<f64>> {
+        let mut offsets = Vec::with_capacity(self.sequence.notes.len());
+        let mut phrases: HashMap<InstrumentId, (f64, f64)> = HashMap::new(); // Start and end of the current legato phrase of each instrument
+        for note in &self.sequence.notes {
+            if self.instruments.get(&note.instrument_id)?.legato_retrigger {
+                offsets.push(0f64);
+                continue;
+            }
+            let phrase = phrases
+                .entry(note.instrument_id)
+                .or_insert((note.start_at, note.end_at));
+            if (phrase.0 < note.start_at) & (note.start_at <= phrase.1) {
+                phrase.1 = phrase.1.max(note.end_at);
+            } else {
+                *phrase = (note.start_at, note.end_at);
+            }
+            offsets.push(note.start_at - phrase.0);
+        }
+        Ok(offsets)
+    }
+    /// Calculates the highest priority of all instruments playing for every frame
+    pub fn calc_priorities(&mut self, nb_frames: usize) -> Result<Vec<u8>> {
+        let sample_rate = self.pcm_parameters.sample_rate;
+        let mut priorities = vec![0u8; nb_frames];
+        for note in &self.sequence.notes {
+            let priority = self.instruments.get(&note.instrument_id)?.priority;
+            let start = time_to_frame(note.start_at, sample_rate)?.min(nb_frames);
+            let end = max(start, time_to_frame(note.end_at, sample_rate)?.min(nb_frames));
+            for max_priority in &mut priorities[start..end] {
+                *max_priority = max(*max_priority, priority);
+            }
+        }
+        Ok(priorities)
+    }
+    /// Generates all frequencies needed for processing, except for frozen Instruments
     pub fn gen_instrument_keys(&mut self) -> Result<()> {
+        self.gen_keys_timed(None, None)
+    }
+    /// Generates all frequencies needed for processing, adding the time spent by each Instrument to the times if given
+    /// and stopping when going past the limits of the watchdog if given
+    fn gen_keys_timed(
+        &mut self,
+        mut times: Option<&mut HashMap<InstrumentId, StageTimes>>,
+        watchdog: Option<&Watchdog>,
+    ) -> Result<()> {
         for (instrument_id, frequencies) in &self.sequence.list_frequencies_for_instruments() {
+            if let Some(w) = watchdog {
+                w.check_time()?;
+            }
+            if self.frozen.contains_key(instrument_id) {
+                continue;
+            }
+            let clock = times.as_ref().map(|_| Instant::now());
             let instrument = self.instruments.get(instrument_id)?;
-            instrument.gen_keys(
+            instrument.gen_keys_with_cache(
                 frequencies,
                 &self.frequency_lut,
                 &PCMParameters {
@@ -243,12 +1209,27 @@ impl MusicSequencer {
                     sample_rate: self.pcm_parameters.sample_rate,
                     sample_type: Sample::Float(0f32),
                 },
+                self.key_cache.as_ref(),
             )?;
+            if let (Some(t), Some(c)) = (times.as_mut(), clock) {
+                t.entry(*instrument_id).or_default().key_generation += c.elapsed();
+            }
         }
         Ok(())
     }
 }
 
+/// Gives the gain to apply on each channel for a pan position. Panning is only done in stereo, every channel is at full gain otherwise.
+fn pan_gains(pan: Option<f64>, nb_channels: usize) -> Vec<f32> {
+    match pan {
+        Some(p) if nb_channels == 2 => {
+            let p = p.clamp(-1f64, 1f64);
+            vec![(1f64 - p).min(1f64) as f32, (1f64 + p).min(1f64) as f32]
+        }
+        _ => vec![1f32; nb_channels],
+    }
+}
+
 impl Sequence {
     /// Creates an empty new Sequence
     pub fn new() -> Sequence {
@@ -265,10 +1246,109 @@ impl Sequence {
     pub fn merge_other(&mut self, other: &mut Sequence) {
         self.notes.append(&mut other.notes);
     }
-    /// Sorts all Notes in the sequence by time
+    /// Sorts all Notes in the sequence by time, notes starting at NaN are put at one end instead of panicking
     pub fn sort_by_time(&mut self) {
+        self.notes.sort_by(|a, b| a.start_at.total_cmp(&b.start_at));
+    }
+    /// Changes every note of the Sequence, then checks that they are all still valid with Note::check.
+    /// If any note is not, the Sequence is left as it was before and the error of the first invalid note is returned.
+    pub fn map_notes<F: FnMut(&mut Note)>(&mut self, mut f: F) -> Result<()> {
+        self.try_map_notes(|n| {
+            f(n);
+            Ok(())
+        })
+    }
+    /// Same as map_notes with a change that can fail, the Sequence is left as it was before if it does
+    pub fn try_map_notes<F: FnMut(&mut Note) -> Result<()>>(&mut self, mut f: F) -> Result<()> {
+        let mut notes = self.notes.clone();
+        for note in &mut notes {
+            f(note)?;
+        }
+        for (note_id, note) in notes.iter().enumerate() {
+            if let Err(e) = note.check() {
+                return Err(SequencerError::InvalidNote(NoteId(note_id), e));
+            }
+        }
+        self.notes = notes;
+        Ok(())
+    }
+    /// Gives the notes starting in a range of bars, bars being counted from 0
+    pub fn notes_in_bars(&self, bars: Range<u32>, tempo_map: &TempoMap) -> Vec<&Note> {
+        let start = tempo_map.bar_beat_to_seconds(bars.start, 0f64);
+        let end = tempo_map.bar_beat_to_seconds(bars.end, 0f64);
         self.notes
-            .sort_by(|a, b| a.start_at.partial_cmp(&b.start_at).unwrap()); // Hopefully nobody decides to put NaNs in the data :)
+            .iter()
+            .filter(|n| (n.start_at >= start) & (n.start_at < end))
+            .collect()
+    }
+    /// Adds a note placed in bars and beats. The times of the given note are replaced, everything else is kept.
+    /// # Arguments
+    /// * tempo_map: Tempo and time signatures to place the note with
+    /// * bar: Bar the note starts in, counted from 0
+    /// * beat: Beat inside of the bar the note starts on, counted from 0
+    /// * length: How long the note is, in beats
+    /// * note: The note to add
+    pub fn insert_note_at_beat(
+        &mut self,
+        tempo_map: &TempoMap,
+        bar: u32,
+        beat: f64,
+        length: f64,
+        note: Note,
+    ) {
+        let start_beat = tempo_map.bars_to_beats(f64::from(bar)) + beat;
+        let start_at = tempo_map.beats_to_seconds(start_beat);
+        let end_at = tempo_map.beats_to_seconds(start_beat + length);
+        self.add_note(Note {
+            start_at,
+            end_at,
+            duration: end_at - start_at,
+            ..note
+        });
+    }
+    /// Makes the Sequence smaller by rounding times to a grid, dropping silent or empty notes and merging identical consecutive notes.
+    /// Notes are identical if they have the same instrument, frequency and on velocity.
+    /// # Arguments
+    /// * tolerance: Longest gap in seconds between two identical notes for them to be merged
+    /// * grid: If set, start and end times are rounded to multiples of this, in seconds. Has to be a positive number.
+    pub fn simplify(&mut self, tolerance: f64, grid: Option<f64>) -> Result<()> {
+        if let Some(g) = grid {
+            if !g.is_finite() | (g <= 0f64) {
+                return Err(SequencerError::InvalidParameter(String::from(
+                    "grid should be a positive number",
+                )));
+            }
+            for note in &mut self.notes {
+                note.start_at = (note.start_at / g).round() * g;
+                note.end_at = (note.end_at / g).round() * g;
+                note.duration = note.end_at - note.start_at;
+            }
+        }
+        self.notes
+            .retain(|n| (n.on_velocity > 0f64) & (n.duration > 0f64));
+        self.sort_by_time();
+        let mut simplified: Vec<Note> = Vec::with_capacity(self.notes.len());
+        let mut last_of_kind: HashMap<(InstrumentId, FrequencyId), usize> = HashMap::new();
+        for note in self.notes.drain(..) {
+            let key = (note.instrument_id, note.frequency_id);
+            if let Some(&id) = last_of_kind.get(&key) {
+                let previous = &mut simplified[id];
+                if (previous.on_velocity == note.on_velocity)
+                    & (note.start_at <= previous.end_at + tolerance)
+                {
+                    if note.end_at > previous.end_at {
+                        previous.end_at = note.end_at;
+                        previous.off_velocity = note.off_velocity;
+                    }
+                    previous.duration = previous.end_at - previous.start_at;
+                    continue;
+                }
+            }
+            last_of_kind.insert(key, simplified.len());
+            simplified.push(note);
+        }
+        self.notes = simplified;
+        Ok(())
     }
     /// Calculates the maximum amount of notes that will be played at once throughout the entire sequence
     pub fn calc_max_notes_at_once(&mut self) -> usize {
@@ -301,7 +1381,9 @@ impl Sequence {
         max_notes
     }
     /// Generates a HashMap containing what frequencies each instrument will be playing and for how long
-    pub fn list_frequencies_for_instruments(&self) -> HashMap<usize, Vec<(usize, f64)>> {
+    pub fn list_frequencies_for_instruments(
+        &self,
+    ) -> HashMap<InstrumentId, Vec<(FrequencyId, f64)>> {
         let mut frequencies_used_by_instruments = HashMap::new();
         for note in &self.notes {
             let frequencies_times = frequencies_used_by_instruments
@@ -309,7 +1391,7 @@ impl Sequence {
                 .or_insert_with(Vec::new);
             match frequencies_times
                 .iter()
-                .position(|x: &(usize, f64)| x.0 == note.frequency_id)
+                .position(|x: &(FrequencyId, f64)| x.0 == note.frequency_id)
             {
                 None => frequencies_times.push((note.frequency_id, note.duration)),
                 Some(id) => {
@@ -335,14 +1417,52 @@ impl Sequence {
     }
 }
 
+impl Note {
+    /// Checks that the note can be played: times are finite and not negative, it ends after it starts,
+    /// its duration matches its start and end, and velocities are finite and not negative.
+    /// Gives what is wrong otherwise.
+    pub fn check(&self) -> std::result::Result<(), String> {
+        if !self.start_at.is_finite() | (self.start_at < 0f64) {
+            return Err(format!("Impossible start: {}", self.start_at));
+        }
+        if !self.end_at.is_finite() | (self.end_at < self.start_at) {
+            return Err(format!("End before start: {}", self.end_at));
+        }
+        if (self.duration - (self.end_at - self.start_at)).abs() > NOTE_DURATION_TOLERANCE {
+            return Err(format!(
+                "Duration {} does not match start and end",
+                self.duration
+            ));
+        }
+        if !self.on_velocity.is_finite() | (self.on_velocity < 0f64) {
+            return Err(format!("Impossible on velocity: {}", self.on_velocity));
+        }
+        if !self.off_velocity.is_finite() | (self.off_velocity < 0f64) {
+            return Err(format!("Impossible off velocity: {}", self.off_velocity));
+        }
+        if !self.detune.is_finite() {
+            return Err(format!("Impossible detune: {}", self.detune));
+        }
+        Ok(())
+    }
+}
+
 impl FrequencyLookupTable {
     pub fn new() -> FrequencyLookupTable {
         FrequencyLookupTable {
             lut: HashMap::new(),
         }
     }
+    /// Creates a FrequencyLookupTable where each frequency is assigned its position in the slice as an ID
+    pub fn from_frequencies(frequencies: &[f64]) -> FrequencyLookupTable {
+        let mut lut = HashMap::new();
+        for (index, value) in frequencies.iter().enumerate() {
+            lut.insert(FrequencyId(index), *value);
+        }
+        FrequencyLookupTable { lut }
+    }
     /// Returns a Frequency for an ID if it exists, otherwise returns an error.
-    pub fn get(&self, id: &usize) -> Result<&f64> {
+    pub fn get(&self, id: &FrequencyId) -> Result<&f64> {
         match self.lut.get(id) {
             Some(v) => {
                 v.check_valid_time_frequency()?;
@@ -364,7 +1484,7 @@ impl LoopInfo {
 
 impl InstrumentTable {
     /// Returns an Instrument from the list from an ID, returns an error if there is no instrument at specified ID
-    pub fn get(&mut self, id: &usize) -> Result<&mut Instrument> {
+    pub fn get(&mut self, id: &InstrumentId) -> Result<&mut Instrument> {
         match self.instruments.get_mut(id) {
             Some(i) => Ok(i),
             None => Err(SequencerError::NoInstrumentForID(*id)),
@@ -380,37 +1500,177 @@ impl Instrument {
     /// * parameters: PCM parameters to use when generating new keys
     pub fn gen_keys(
         &mut self,
-        frequency_ids_durations: &[(usize, f64)],
+        frequency_ids_durations: &[(FrequencyId, f64)],
+        f_lut: &FrequencyLookupTable,
+        parameters: &PCMParameters,
+    ) -> Result<()> {
+        self.gen_keys_with_cache(frequency_ids_durations, f_lut, parameters, None)
+    }
+    /// Same as gen_keys, loading keys from a cache if given and storing the ones generated in it.
+    /// Only keys of Key Generators giving a cache ID are cached. Keys that cannot be stored are only left out of the cache.
+    pub fn gen_keys_with_cache(
+        &mut self,
+        frequency_ids_durations: &[(FrequencyId, f64)],
         f_lut: &FrequencyLookupTable,
         parameters: &PCMParameters,
+        cache: Option<&KeyCache>,
     ) -> Result<()> {
         match self.key_generator {
             Some(ref g) => {
+                let cache_id = cache.and_then(|_| g.cache_id());
                 for frequency_id in frequency_ids_durations {
-                    self.keys.insert(
-                        frequency_id.0,
-                        g.key_gen(f_lut.get(&frequency_id.0)?, parameters, &frequency_id.1),
-                    );
+                    let frequency = match self.resolve_frequency(*f_lut.get(&frequency_id.0)?)? {
+                        Some(f) => f,
+                        None => continue,
+                    };
+                    let duration = (frequency_id.1 + self.release_duration())
+                        * self.playback.as_ref().map_or(1f64, |p| p.source_rate())
+                        + self.latency();
+                    let key = match (cache, &cache_id) {
+                        (Some(c), Some(id)) => match c.load(id, frequency, parameters, duration) {
+                            Some(k) => k,
+                            None => {
+                                let key = g.key_gen(&frequency, parameters, &duration);
+                                // A key that cannot be stored is generated again next time, the render goes on
+                                let _ = c.store(id, parameters, duration, &key);
+                                key
+                            }
+                        },
+                        _ => g.key_gen(&frequency, parameters, &duration),
+                    };
+                    let key = match self.playback {
+                        // The latency is skipped first, it would be stretched or moved to the end otherwise
+                        Some(ref p) => {
+                            let sample_rate = key.audio.parameters.sample_rate;
+                            let latency_frames = time_to_frame(self.latency(), sample_rate)?
+                                .min(key.audio.frames.len());
+                            let frames = p.apply(
+                                &key.audio.frames[latency_frames..],
+                                sample_rate,
+                                key.audio.parameters.nb_channels as usize,
+                            )?;
+                            Key {
+                                audio: PCM { frames, ..key.audio },
+                                frequency: key.frequency,
+                            }
+                        }
+                        None => key,
+                    };
+                    self.keys.insert(frequency_id.0, key);
                 }
             }
             None => {
                 let pitch_changer = KeyPitchChanger {
                     original_key: self.get_any_key()?.clone(),
+                    glide: None,
                 };
                 for frequency_id in frequency_ids_durations {
+                    let frequency = match self.resolve_frequency(*f_lut.get(&frequency_id.0)?)? {
+                        Some(f) => f,
+                        None => continue,
+                    };
                     self.keys.insert(
                         frequency_id.0,
-                        pitch_changer.key_gen(
-                            f_lut.get(&frequency_id.0)?,
-                            parameters,
-                            &frequency_id.1,
-                        ),
+                        pitch_changer.change_pitch(frequency, parameters)?,
                     );
                 }
             }
         }
         Ok(())
     }
+    /// Gives the frequency this instrument actually plays for a note, following its KeyRange.
+    /// Returns None if the note should not be played.
+    pub fn resolve_frequency(&self, frequency: f64) -> Result<Option<f64>> {
+        let range = match self.key_range {
+            Some(ref r) => r,
+            None => return Ok(Some(frequency)),
+        };
+        range.check()?;
+        if (range.low <= frequency) & (frequency <= range.high) {
+            return Ok(Some(frequency));
+        }
+        match range.out_of_range {
+            OutOfRangePolicy::Transpose => {
+                let mut transposed = frequency;
+                let mut octaves = 0u32;
+                while transposed < range.low {
+                    transposed *= 2f64;
+                    octaves += 1;
+                    if octaves > MAX_TRANSPOSE_OCTAVES {
+                        return Err(SequencerError::FrequencyOutOfRange(frequency));
+                    }
+                }
+                while transposed > range.high {
+                    transposed /= 2f64;
+                    octaves += 1;
+                    if octaves > MAX_TRANSPOSE_OCTAVES {
+                        return Err(SequencerError::FrequencyOutOfRange(frequency));
+                    }
+                }
+                if transposed < range.low {
+                    // Range narrower than an octave, take the octave closest to the range
+                    let above = transposed * 2f64;
+                    if (above / range.high) < (range.low / transposed) {
+                        transposed = above;
+                    }
+                }
+                Ok(Some(transposed))
+            }
+            OutOfRangePolicy::Drop => Ok(None),
+            OutOfRangePolicy::Error => Err(SequencerError::FrequencyOutOfRange(frequency)),
+        }
+    }
+    /// Gives the amplitude of a note played at a velocity
+    pub fn velocity_amplitude(&self, velocity: f64) -> f64 {
+        match self.velocity_to_amplitude {
+            Some(ref m) => m.map(velocity),
+            None => velocity,
+        }
+    }
+    /// How long this instrument keeps playing after a note is released, in seconds
+    pub fn release_duration(&self) -> f64 {
+        match self.envelope {
+            Some(ref e) => e.release_duration(),
+            None => 0f64,
+        }
+    }
+    /// Delay before the sound of the keys of this instrument starts, in seconds, compensated when generating sounds
+    pub fn latency(&self) -> f64 {
+        match self.key_generator {
+            Some(ref g) => g.latency(),
+            None => 0f64,
+        }
+    }
+    /// Returns the amplitude given by the Envelope at a particular time, or full loudness if there is no Envelope.
+    /// # Arguments
+    /// * time: In seconds, the position to get the amplitude for
+    /// * released: Whether the key has been released, time is then counted from the release
+    pub fn envelope_amplitude(&self, time: &f64, released: bool) -> Result<f64> {
+        let amplitude = match self.envelope {
+            Some(ref e) => {
+                if released {
+                    e.after_sustain(time)
+                } else {
+                    e.before_during_sustain(time)
+                }
+            }
+            None => 1f64,
+        };
+        checks::check_envelope_output(amplitude)?;
+        Ok(amplitude)
+    }
+    /// Gives the amplitude of the Envelope at a time after the key is released, starting from the level it had when released
+    /// # Arguments
+    /// * time: Time since the release in seconds
+    /// * released_at: Time from the start of the Envelope at which the key was released, in seconds
+    pub fn release_amplitude(&self, time: &f64, released_at: &f64) -> Result<f64> {
+        let amplitude = match self.envelope {
+            Some(ref e) => e.release_from(time, e.before_during_sustain(released_at)),
+            None => 1f64,
+        };
+        checks::check_envelope_output(amplitude)?;
+        Ok(amplitude)
+    }
     /// Returns any first key that is available, used for the pitch changer.
     pub fn get_any_key(&self) -> Result<&Key> {
         Ok(match self.keys.values().next() {
@@ -418,26 +1678,59 @@ impl Instrument {
             None => return Err(SequencerError::NoDefaultKeyGiven),
         })
     }
-    pub fn gen_sound(&self, frequency_id: &usize, duration: &f64) -> Result<PCM> {
+    /// Generates the sound of a note from a Key, with the envelope applied and followed by the release.
+    /// The latency of the Key Generator is skipped.
+    /// # Arguments
+    /// * frequency_id: ID of the Key to use
+    /// * duration: How long the note is held for, not including the release
+    /// * envelope_offset: Time at which the envelope starts, 0 unless the envelope continues from a previous note
+    pub fn gen_sound(
+        &self,
+        frequency_id: &FrequencyId,
+        duration: &f64,
+        envelope_offset: &f64,
+    ) -> Result<PCM> {
         duration.check_valid_time_frequency()?;
         let key = match self.keys.get(frequency_id) {
             Some(k) => k,
             None => return Err(SequencerError::NoKeyForID(*frequency_id)),
         };
-        let needed_frames = (duration * f64::from(key.audio.parameters.sample_rate)) as usize;
+        let needed_frames = duration_to_frames(
+            duration + self.release_duration(),
+            key.audio.parameters.sample_rate,
+        )?;
+        let modified;
+        let (frames, latency_frames) = match self.playback {
+            // Keys of Key Generators were modified when generated, with their latency already skipped.
+            // Without a Key Generator, keys are made from the ones given by hand, so they are kept as they are and modified here.
+            Some(ref p) if self.key_generator.is_none() => {
+                modified = p.apply(
+                    &key.audio.frames,
+                    key.audio.parameters.sample_rate,
+                    key.audio.parameters.nb_channels as usize,
+                )?;
+                (&modified[..], 0)
+            }
+            Some(_) => (&key.audio.frames[..], 0),
+            None => (
+                &key.audio.frames[..],
+                time_to_frame(self.latency(), key.audio.parameters.sample_rate)?,
+            ),
+        };
+        if frames.is_empty() {
+            return Err(SequencerError::EmptyKey(*frequency_id));
+        }
         let mut final_sound: Vec<Frame> = Vec::with_capacity(needed_frames);
-        let mut frame_position = 0usize;
+        let mut frame_position = latency_frames;
         if self.loopable {
-            while frame_position < needed_frames {
-                final_sound.push(
-                    key.audio.frames[(frame_position % (key.audio.frames.len() - 1))].clone(),
-                );
+            while frame_position < latency_frames + needed_frames {
+                final_sound.push(frames[(frame_position % (frames.len() - 1).max(1))].clone());
                 frame_position += 1;
             }
         } else {
-            let mut last_frame = &key.audio.frames[0];
-            while frame_position < needed_frames {
-                final_sound.push(match key.audio.frames.get(frame_position) {
+            let mut last_frame = &frames[latency_frames.min(frames.len() - 1)];
+            while frame_position < latency_frames + needed_frames {
+                final_sound.push(match frames.get(frame_position) {
                     Some(f) => {
                         last_frame = f;
                         f.clone()
@@ -447,6 +1740,25 @@ impl Instrument {
                 frame_position += 1;
             }
         }
+        if let Some(ref l) = self.low_bit {
+            l.apply(&mut final_sound, key.audio.parameters.sample_rate)?;
+        }
+        if self.envelope.is_some() {
+            let sample_rate = f64::from(key.audio.parameters.sample_rate);
+            for (frame_id, frame) in final_sound.iter_mut().enumerate() {
+                let time = frame_id as f64 / sample_rate;
+                let amplitude = if time < *duration {
+                    self.envelope_amplitude(&(envelope_offset + time), false)?
+                } else {
+                    self.release_amplitude(&(time - duration), &(envelope_offset + duration))?
+                } as f32;
+                for sample in &mut frame.samples {
+                    if let Sample::Float(s) = *sample {
+                        *sample = Sample::Float(s * amplitude);
+                    }
+                }
+            }
+        }
         Ok(PCM {
             parameters: key.audio.parameters.clone(),
             loop_info: key.audio.loop_info.clone(),
@@ -455,8 +1767,230 @@ impl Instrument {
     }
 }
 
+impl KeyPitchChanger {
+    /// Gives the original key at another frequency and sample rate, gliding to it if a glide is set.
+    /// Gives an error if the frequency of the original key or the new frequency is not valid.
+    pub fn change_pitch(&self, frequency: f64, parameters: &PCMParameters) -> Result<Key> {
+        let original = &self.original_key;
+        original.frequency.check_valid_time_frequency()?;
+        frequency.check_valid_time_frequency()?;
+        let rate_ratio =
+            f64::from(original.audio.parameters.sample_rate) / f64::from(parameters.sample_rate);
+        let step = rate_ratio * frequency / original.frequency;
+        let nb_channels = original.audio.parameters.nb_channels as usize;
+        let frames = match self.glide {
+            Some(g) => pitch::resample_frames_gliding(
+                &original.audio.frames,
+                rate_ratio * pitch::shift_by_cents(frequency, g.from_cents) / original.frequency,
+                step,
+                duration_to_frames(g.duration, parameters.sample_rate)?,
+                g.interpolation,
+                nb_channels,
+            )?,
+            None => pitch::resample_frames(&original.audio.frames, step, nb_channels)?,
+        };
+        Ok(Key {
+            frequency,
+            audio: PCM {
+                parameters: PCMParameters {
+                    nb_channels: original.audio.parameters.nb_channels,
+                    ..parameters.clone()
+                },
+                loop_info: None,
+                frames,
+            },
+        })
+    }
+}
+
 impl KeyGenerator for KeyPitchChanger {
-    fn key_gen(&self, _frequency: &f64, _parameters: &PCMParameters, _duration: &f64) -> Key {
-        unimplemented!("Cannot change the pitch of a Key for now")
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, _duration: &f64) -> Key {
+        // Key Generators cannot give errors, an invalid frequency gives an empty key reported as EmptyKey when played
+        self.change_pitch(*frequency, parameters)
+            .unwrap_or_else(|_| Key {
+                frequency: *frequency,
+                audio: PCM {
+                    parameters: parameters.clone(),
+                    loop_info: None,
+                    frames: Vec::new(),
+                },
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envelopes::AdsrEnvelope;
+
+    /// Sample rates every envelope has to give the same levels at
+    const SAMPLE_RATES: [u32; 4] = [22_050, 44_100, 48_000, 96_000];
+
+    /// Gives an Instrument holding a single key at full amplitude, one second long
+    fn flat_instrument(sample_rate: u32, envelope: AdsrEnvelope) -> Instrument {
+        let parameters = PCMParameters {
+            sample_rate,
+            nb_channels: 1,
+            sample_type: Sample::Float(0f32),
+        };
+        let mut keys = HashMap::new();
+        keys.insert(
+            FrequencyId(0),
+            Key {
+                audio: PCM {
+                    parameters,
+                    loop_info: None,
+                    frames: vec![
+                        Frame {
+                            samples: vec![Sample::Float(1f32)],
+                        };
+                        sample_rate as usize
+                    ],
+                },
+                frequency: 440f64,
+            },
+        );
+        Instrument {
+            keys,
+            key_generator: None,
+            loopable: true,
+            envelope: Some(Box::new(envelope)),
+            auto_pan: None,
+            priority: 0,
+            low_bit: None,
+            legato_retrigger: true,
+            key_range: None,
+            layers: Vec::new(),
+            velocity_to_amplitude: None,
+            playback: None,
+        }
+    }
+
+    /// Gives a note of the first Instrument
+    fn note(start_at: f64, end_at: f64, frequency_id: usize, on_velocity: f64) -> Note {
+        Note {
+            start_at,
+            end_at,
+            duration: end_at - start_at,
+            frequency_id: FrequencyId(frequency_id),
+            on_velocity,
+            off_velocity: 0f64,
+            instrument_id: InstrumentId(0),
+            detune: 0f64,
+        }
+    }
+
+    /// Gives the start, end and frequency of every note of a Sequence
+    fn timings(sequence: &Sequence) -> Vec<(f64, f64, usize)> {
+        sequence
+            .notes
+            .iter()
+            .map(|n| (n.start_at, n.end_at, n.frequency_id.0))
+            .collect()
+    }
+
+    #[test]
+    fn simplify_merges_quantizes_and_drops_notes() {
+        let mut sequence = Sequence::new();
+        // Two touching notes of the same key and velocity, merged into one
+        sequence.add_note(note(0f64, 1f64, 0, 1f64));
+        sequence.add_note(note(1.01f64, 2f64, 0, 1f64));
+        // Same key but another velocity, kept apart
+        sequence.add_note(note(2f64, 3f64, 0, 0.5f64));
+        // Silent and empty notes, dropped
+        sequence.add_note(note(0f64, 1f64, 1, 0f64));
+        sequence.add_note(note(0.5f64, 0.5f64, 1, 1f64));
+        // Another key, moved to the grid
+        sequence.add_note(note(0.49f64, 1.26f64, 2, 1f64));
+        sequence.simplify(0.05f64, None).unwrap();
+        assert_eq!(
+            timings(&sequence),
+            vec![(0f64, 2f64, 0), (0.49f64, 1.26f64, 2), (2f64, 3f64, 0)]
+        );
+        sequence.simplify(0f64, Some(0.25f64)).unwrap();
+        assert_eq!(
+            timings(&sequence),
+            vec![(0f64, 2f64, 0), (0.5f64, 1.25f64, 2), (2f64, 3f64, 0)]
+        );
+        for grid in &[0f64, -1f64, ::std::f64::NAN] {
+            assert!(sequence.simplify(0f64, Some(*grid)).is_err());
+        }
+    }
+
+    #[test]
+    fn try_map_notes_leaves_the_sequence_unchanged_on_error() {
+        let mut sequence = Sequence::new();
+        sequence.add_note(note(0f64, 1f64, 0, 1f64));
+        sequence.add_note(note(1f64, 2f64, 1, 1f64));
+        // An error from the closure, after the first note was changed
+        let result = sequence.try_map_notes(|n| {
+            if n.frequency_id == FrequencyId(1) {
+                return Err(SequencerError::NoFrequencyForID(n.frequency_id));
+            }
+            n.start_at += 0.5f64;
+            n.duration -= 0.5f64;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(timings(&sequence), vec![(0f64, 1f64, 0), (1f64, 2f64, 1)]);
+        // A note made invalid by the closure, with the index of the note in the error
+        match sequence.try_map_notes(|n| {
+            if n.frequency_id == FrequencyId(1) {
+                n.end_at = 0f64;
+            }
+            n.on_velocity = 0.5f64;
+            Ok(())
+        }) {
+            Err(SequencerError::InvalidNote(NoteId(1), _)) => {}
+            _ => panic!("The second note should be invalid"),
+        }
+        assert_eq!(timings(&sequence), vec![(0f64, 1f64, 0), (1f64, 2f64, 1)]);
+        assert!(sequence.notes.iter().all(|n| n.on_velocity == 1f64));
+        sequence
+            .try_map_notes(|n| {
+                n.on_velocity = 0.5f64;
+                Ok(())
+            })
+            .unwrap();
+        assert!(sequence.notes.iter().all(|n| n.on_velocity == 0.5f64));
+    }
+
+    #[test]
+    fn envelope_levels_across_sample_rates() {
+        let envelope = AdsrEnvelope {
+            attack: 0.01f64,
+            decay: 0.1f64,
+            sustain: 0.5f64,
+            release: 0.2f64,
+        };
+        let duration = 0.3f64;
+        // Time in seconds along with the expected level, in the attack, the decay, the sustain and the release
+        let checks = [
+            (0.005f64, 0.5f64),
+            (0.06f64, 0.75f64),
+            (0.2f64, 0.5f64),
+            (0.4f64, 0.25f64),
+        ];
+        // The level changes by at most this much per second, during the attack
+        let steepest_slope = envelope.attack.recip();
+        for &sample_rate in &SAMPLE_RATES {
+            let instrument = flat_instrument(sample_rate, envelope.clone());
+            let sound = instrument.gen_sound(&FrequencyId(0), &duration, &0f64).unwrap();
+            for &(time, expected) in &checks {
+                let frame_id = (time * f64::from(sample_rate)).round() as usize;
+                let level = match sound.frames[frame_id].samples[0] {
+                    Sample::Float(s) => f64::from(s),
+                    _ => panic!("Sounds should have Float samples"),
+                };
+                assert!(
+                    (level - expected).abs() < steepest_slope / f64::from(sample_rate),
+                    "Level of {} at {}s and {}Hz instead of {}",
+                    level,
+                    time,
+                    sample_rate,
+                    expected
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,84 @@
+use error::SequencerError;
+use pcm::{Frame, Sample};
+use Result;
+
+/// Emulates the low bit-depth sample channels of old consoles, like the DPCM channel of the NES
+#[derive(Clone)]
+pub struct LowBitPlayback {
+    /// How samples are quantized
+    pub mode: LowBitMode,
+    /// Rate at which the channel updates its output, in Hertz. Has to be a positive number.
+    pub rate: f64,
+}
+
+/// Quantization used by a LowBitPlayback
+#[derive(Clone)]
+pub enum LowBitMode {
+    /// 1-bit delta modulation, the output goes up or down by one step on a 7-bit counter at every update (NES DPCM)
+    Delta,
+    /// 4-bit PCM, the output is rounded to one of 16 levels at every update
+    FourBit,
+}
+
+/// Highest value of the 7-bit counter used by the delta mode
+const DELTA_MAX: i32 = 127;
+/// How much the 7-bit counter moves at every update in delta mode
+const DELTA_STEP: i32 = 2;
+/// Highest level in 4-bit mode
+const FOUR_BIT_MAX: f64 = 15f64;
+
+impl LowBitPlayback {
+    /// Quantizes frames in place. Only Float samples are processed.
+    /// Gives an error if the rate is not a positive number.
+    pub fn apply(&self, frames: &mut [Frame], sample_rate: u32) -> Result<()> {
+        if !self.rate.is_finite() | (self.rate <= 0f64) {
+            return Err(SequencerError::InvalidParameter(format!(
+                "low bit rate {} should be a positive number",
+                self.rate
+            )));
+        }
+        let sample_rate_period = f64::from(sample_rate).recip(); // In seconds
+        let update_period = self.rate.recip(); // In seconds
+        let mut counters: Vec<i32> = Vec::new();
+        let mut held: Vec<f32> = Vec::new();
+        let mut since_update = update_period; // Updates on the first frame
+        for frame in frames {
+            let update = since_update >= update_period;
+            if update {
+                since_update %= update_period;
+            }
+            for (channel, sample) in frame.samples.iter_mut().enumerate() {
+                if let Sample::Float(s) = *sample {
+                    if counters.len() <= channel {
+                        counters.resize(channel + 1, DELTA_MAX / 2);
+                        held.resize(channel + 1, 0f32);
+                    }
+                    if update {
+                        held[channel] = match self.mode {
+                            LowBitMode::Delta => {
+                                let target = (f64::from(s) + 1f64) * f64::from(DELTA_MAX) / 2f64;
+                                if target > f64::from(counters[channel]) {
+                                    counters[channel] =
+                                        (counters[channel] + DELTA_STEP).min(DELTA_MAX);
+                                } else {
+                                    counters[channel] = (counters[channel] - DELTA_STEP).max(0);
+                                }
+                                (f64::from(counters[channel]) * 2f64 / f64::from(DELTA_MAX)
+                                    - 1f64) as f32
+                            }
+                            LowBitMode::FourBit => {
+                                let level = ((f64::from(s) + 1f64) / 2f64 * FOUR_BIT_MAX)
+                                    .round()
+                                    .clamp(0f64, FOUR_BIT_MAX);
+                                (level * 2f64 / FOUR_BIT_MAX - 1f64) as f32
+                            }
+                        };
+                    }
+                    *sample = Sample::Float(held[channel]);
+                }
+            }
+            since_update += sample_rate_period;
+        }
+        Ok(())
+    }
+}
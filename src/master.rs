@@ -0,0 +1,73 @@
+use filters::Biquad;
+use pcm::{Frame, Sample};
+
+/// Gain trim and equalizer of one output channel, applied on the final mix.
+/// Useful for playback systems with known imbalances between speakers, or for deliberately asymmetric lo-fi output.
+#[derive(Clone, Default)]
+pub struct ChannelStrip {
+    /// Gain in dB, applied after the equalizer. 0 leaves the level untouched.
+    pub trim: f64,
+    /// Equalizer bands, applied in order
+    pub eq: Vec<EqBand>,
+}
+
+/// One band of the equalizer of a ChannelStrip. Frequencies are in Hertz and gains in dB.
+#[derive(Clone, Copy, Debug)]
+pub enum EqBand {
+    /// Changes the level of everything below a frequency
+    LowShelf { frequency: f64, gain: f64, q: f64 },
+    /// Changes the level of a band around a frequency, narrower with a higher q
+    Peaking { frequency: f64, gain: f64, q: f64 },
+    /// Changes the level of everything above a frequency
+    HighShelf { frequency: f64, gain: f64, q: f64 },
+}
+
+impl EqBand {
+    /// Gives the filter of this band at a sample rate
+    pub fn filter(&self, sample_rate: f64) -> Biquad {
+        match *self {
+            EqBand::LowShelf { frequency, gain, q } => {
+                Biquad::low_shelf(frequency, gain, q, sample_rate)
+            }
+            EqBand::Peaking { frequency, gain, q } => {
+                Biquad::peaking(frequency, gain, q, sample_rate)
+            }
+            EqBand::HighShelf { frequency, gain, q } => {
+                Biquad::high_shelf(frequency, gain, q, sample_rate)
+            }
+        }
+    }
+}
+
+impl ChannelStrip {
+    /// Processes one channel of frames in place. Only Float samples are processed.
+    /// # Arguments
+    /// * frames: Frames to process
+    /// * channel: Index of the channel in the frames
+    /// * sample_rate: Sample rate of the frames
+    pub fn process(&self, frames: &mut [Frame], channel: usize, sample_rate: u32) {
+        let mut filters: Vec<Biquad> = self
+            .eq
+            .iter()
+            .map(|b| b.filter(f64::from(sample_rate)))
+            .collect();
+        let gain = 10f64.powf(self.trim / 20f64);
+        for frame in frames {
+            if let Some(Sample::Float(s)) = frame.samples.get_mut(channel) {
+                let mut value = f64::from(*s);
+                for filter in &mut filters {
+                    value = filter.process(value);
+                }
+                *s = (value * gain) as f32;
+            }
+        }
+    }
+}
+
+/// Applies a ChannelStrip on each channel of frames, the first strip on the first channel and so on.
+/// Channels without a strip are left untouched, strips without a channel are ignored.
+pub fn apply_channel_strips(strips: &[ChannelStrip], frames: &mut [Frame], sample_rate: u32) {
+    for (channel, strip) in strips.iter().enumerate() {
+        strip.process(frames, channel, sample_rate);
+    }
+}
@@ -0,0 +1,256 @@
+use adapters::SampleAccess;
+use pcm::PCM;
+use std::f64::consts::PI;
+use {time_to_frame, Result};
+
+/// Amount of harmonics compared, fundamental included
+const NB_HARMONICS: usize = 8;
+/// Below this share of the energy in the harmonics, a sound is considered noise
+const NOISE_THRESHOLD: f64 = 0.3f64;
+/// Length of the blocks used for following the level of a note, in seconds
+const LEVEL_BLOCK: f64 = 0.005f64;
+/// Share of the peak level at which the attack is considered finished
+const ATTACK_END: f64 = 0.9f64;
+
+/// Part of a recording where a single note plays
+#[derive(Clone)]
+pub struct NoteRegion {
+    /// Time at which the note starts, in seconds
+    pub start: f64,
+    /// Time at which the note stops, in seconds
+    pub end: f64,
+    /// Frequency of the note in Hertz
+    pub frequency: f64,
+}
+
+/// Waveforms that can be recognized from their harmonics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    /// Only the fundamental, like the SineWaveGenerator
+    Sine,
+    /// Odd harmonics falling with their number, like the SquareWaveGenerator
+    Square,
+    /// Little energy in the harmonics, like the LfsrNoiseGenerator
+    Noise,
+}
+
+/// Differences between the reference and the attempt for a note, and what to change to get closer
+#[derive(Clone, Debug)]
+pub struct MatchSuggestion {
+    /// Waveform the reference sounds closest to
+    pub waveform: Waveform,
+    /// Distance between the harmonic profiles of the reference and the attempt, 0 if identical and up to 2
+    pub harmonic_distance: f64,
+    /// Time in seconds to add to the attack of the attempt, negative to shorten it
+    pub attack_adjustment: f64,
+    /// Time in seconds to add to the decay of the attempt, negative to shorten it
+    pub decay_adjustment: f64,
+    /// Gain to apply on the attempt to reach the level of the reference
+    pub gain_adjustment: f64,
+}
+
+/// Compares a reference recording to a rendered attempt at recreating it, note by note.
+/// Both are mixed down to mono before comparing.
+/// # Arguments
+/// * reference: The recording to match
+/// * attempt: The rendered attempt, aligned in time with the reference
+/// * regions: Where single notes play, in both the reference and the attempt
+pub fn compare(
+    reference: &PCM,
+    attempt: &PCM,
+    regions: &[NoteRegion],
+) -> Result<Vec<MatchSuggestion>> {
+    let reference_mono = mono(reference)?;
+    let attempt_mono = mono(attempt)?;
+    let reference_rate = f64::from(reference.parameters.sample_rate);
+    let attempt_rate = f64::from(attempt.parameters.sample_rate);
+    let mut suggestions = Vec::with_capacity(regions.len());
+    for region in regions {
+        let reference_samples = slice(&reference_mono, reference.parameters.sample_rate, region)?;
+        let attempt_samples = slice(&attempt_mono, attempt.parameters.sample_rate, region)?;
+        let (reference_profile, reference_harmonic_share) =
+            harmonic_profile(reference_samples, reference_rate, region.frequency);
+        let (attempt_profile, _) =
+            harmonic_profile(attempt_samples, attempt_rate, region.frequency);
+        let (reference_attack, reference_decay) = envelope_times(reference_samples, reference_rate);
+        let (attempt_attack, attempt_decay) = envelope_times(attempt_samples, attempt_rate);
+        let attempt_rms = rms(attempt_samples);
+        suggestions.push(MatchSuggestion {
+            waveform: closest_waveform(&reference_profile, reference_harmonic_share),
+            harmonic_distance: profile_distance(&reference_profile, &attempt_profile),
+            attack_adjustment: reference_attack - attempt_attack,
+            decay_adjustment: reference_decay - attempt_decay,
+            gain_adjustment: if attempt_rms > 0f64 {
+                rms(reference_samples) / attempt_rms
+            } else {
+                1f64
+            },
+        });
+    }
+    Ok(suggestions)
+}
+
+/// Gives the waveform suggested by most notes, along with the average of the adjustments of all notes.
+/// Returns None if there are no suggestions.
+pub fn summarize(suggestions: &[MatchSuggestion]) -> Option<MatchSuggestion> {
+    if suggestions.is_empty() {
+        return None;
+    }
+    let nb = suggestions.len() as f64;
+    let waveform = [Waveform::Sine, Waveform::Square, Waveform::Noise]
+        .iter()
+        .max_by_key(|w| suggestions.iter().filter(|s| s.waveform == **w).count())
+        .cloned()?;
+    Some(MatchSuggestion {
+        waveform,
+        harmonic_distance: suggestions.iter().map(|s| s.harmonic_distance).sum::<f64>() / nb,
+        attack_adjustment: suggestions.iter().map(|s| s.attack_adjustment).sum::<f64>() / nb,
+        decay_adjustment: suggestions.iter().map(|s| s.decay_adjustment).sum::<f64>() / nb,
+        gain_adjustment: suggestions.iter().map(|s| s.gain_adjustment).sum::<f64>() / nb,
+    })
+}
+
+/// Mixes all channels of a PCM into one
+fn mono(pcm: &PCM) -> Result<Vec<f64>> {
+    let nb_channels = (pcm.parameters.nb_channels as usize).max(1);
+    Ok(pcm
+        .interleaved_f64()?
+        .chunks(nb_channels)
+        .map(|frame| frame.iter().sum::<f64>() / nb_channels as f64)
+        .collect())
+}
+
+/// Gives the samples of a region, cut to what is available
+fn slice<'a>(samples: &'a [f64], sample_rate: u32, region: &NoteRegion) -> Result<&'a [f64]> {
+    let start = time_to_frame(region.start, sample_rate)?.min(samples.len());
+    let end = time_to_frame(region.end, sample_rate)?
+        .min(samples.len())
+        .max(start);
+    Ok(&samples[start..end])
+}
+
+/// Root mean square of samples, 0 if there are none
+fn rms(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0f64;
+    }
+    (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+/// Measures the power at a frequency with the Goertzel algorithm
+fn goertzel_power(samples: &[f64], sample_rate: f64, frequency: f64) -> f64 {
+    let coefficient = 2f64 * (2f64 * PI * frequency / sample_rate).cos();
+    let (mut previous, mut before_previous) = (0f64, 0f64);
+    for sample in samples {
+        let current = sample + coefficient * previous - before_previous;
+        before_previous = previous;
+        previous = current;
+    }
+    previous * previous + before_previous * before_previous
+        - coefficient * previous * before_previous
+}
+
+/// Gives the amplitudes of the harmonics of a note normalized so that the largest is 1, along with the share of the energy they contain.
+/// Harmonics above the Nyquist frequency are 0.
+fn harmonic_profile(samples: &[f64], sample_rate: f64, frequency: f64) -> (Vec<f64>, f64) {
+    let mut profile: Vec<f64> = (1..=NB_HARMONICS)
+        .map(|h| {
+            let harmonic = frequency * h as f64;
+            if harmonic < sample_rate / 2f64 {
+                goertzel_power(samples, sample_rate, harmonic)
+            } else {
+                0f64
+            }
+        })
+        .collect();
+    // Goertzel power of a sine of amplitude A over N samples is (A * N / 2)^2
+    let total = samples.iter().map(|s| s * s).sum::<f64>() * samples.len() as f64 / 2f64;
+    let harmonic_share = if total > 0f64 {
+        (profile.iter().sum::<f64>() / total).min(1f64)
+    } else {
+        0f64
+    };
+    for power in &mut profile {
+        *power = power.sqrt();
+    }
+    let largest = profile.iter().cloned().fold(0f64, f64::max);
+    if largest > 0f64 {
+        for amplitude in &mut profile {
+            *amplitude /= largest;
+        }
+    }
+    (profile, harmonic_share)
+}
+
+/// Gives the normalized harmonic profile of a waveform
+fn waveform_profile(waveform: Waveform) -> Vec<f64> {
+    (1..=NB_HARMONICS)
+        .map(|h| match waveform {
+            Waveform::Sine => {
+                if h == 1 {
+                    1f64
+                } else {
+                    0f64
+                }
+            }
+            Waveform::Square => {
+                if h % 2 == 1 {
+                    (h as f64).recip()
+                } else {
+                    0f64
+                }
+            }
+            Waveform::Noise => 0f64,
+        })
+        .collect()
+}
+
+/// Euclidean distance between two harmonic profiles
+fn profile_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// Finds the waveform whose harmonics are closest to a profile
+fn closest_waveform(profile: &[f64], harmonic_share: f64) -> Waveform {
+    if harmonic_share < NOISE_THRESHOLD {
+        return Waveform::Noise;
+    }
+    let sine = profile_distance(profile, &waveform_profile(Waveform::Sine));
+    let square = profile_distance(profile, &waveform_profile(Waveform::Square));
+    if sine <= square {
+        Waveform::Sine
+    } else {
+        Waveform::Square
+    }
+}
+
+/// Measures the attack, the time to reach the peak level, and the decay, the time from the peak to the sustain level, in seconds.
+/// The sustain level is the median level of the second half of the note.
+fn envelope_times(samples: &[f64], sample_rate: f64) -> (f64, f64) {
+    let block_size = ((LEVEL_BLOCK * sample_rate) as usize).max(1);
+    let levels: Vec<f64> = samples.chunks(block_size).map(rms).collect();
+    if levels.is_empty() {
+        return (0f64, 0f64);
+    }
+    let block_duration = block_size as f64 / sample_rate;
+    let peak = levels.iter().cloned().fold(0f64, f64::max);
+    let attack_blocks = levels
+        .iter()
+        .position(|l| *l >= peak * ATTACK_END)
+        .unwrap_or(0);
+    let mut second_half = levels[levels.len() / 2..].to_vec();
+    second_half.sort_by(|a, b| a.total_cmp(b));
+    let sustain = second_half[second_half.len() / 2];
+    let decay_blocks = levels[attack_blocks..]
+        .iter()
+        .position(|l| *l <= sustain + (peak - sustain) * (1f64 - ATTACK_END))
+        .unwrap_or(0);
+    (
+        attack_blocks as f64 * block_duration,
+        decay_blocks as f64 * block_duration,
+    )
+}
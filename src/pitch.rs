@@ -0,0 +1,309 @@
+use error::SequencerError;
+use pcm::{Frame, Sample};
+use Result;
+
+/// Amount of cents in an octave
+pub const CENTS_PER_OCTAVE: f64 = 1200f64;
+/// Frequency of A4 in Hertz, reference for note names
+pub const A4_FREQUENCY: f64 = 440f64;
+/// MIDI note number of A4
+const A4_NOTE_NUMBER: i32 = 69;
+/// Names of the notes in an octave, starting from C
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+/// Largest difference in cents between a frequency and a note for the frequency to be given that name
+const NOTE_NAME_TOLERANCE: f64 = 0.000_001f64;
+
+/// How a pitch moves between two frequencies
+#[derive(Clone, Copy)]
+pub enum PitchInterpolation {
+    /// Linear in Hertz, goes through the low notes faster than the high ones
+    Hertz,
+    /// Linear in cents, musically even, every semitone takes the same time
+    Cents,
+}
+
+impl PitchInterpolation {
+    /// Gives the frequency at a position between two frequencies
+    /// # Arguments
+    /// * from: Frequency at position 0
+    /// * to: Frequency at position 1
+    /// * position: Between 0 and 1
+    pub fn interpolate(self, from: f64, to: f64, position: f64) -> f64 {
+        match self {
+            PitchInterpolation::Hertz => interpolate_hertz(from, to, position),
+            PitchInterpolation::Cents => interpolate_cents(from, to, position),
+        }
+    }
+}
+
+/// Bend at the start of a sound, moving from an offset to the pitch of the sound, like a scoop into a note
+#[derive(Clone, Copy)]
+pub struct PitchGlide {
+    /// Offset in cents from the pitch of the sound at its start, negative to start lower
+    pub from_cents: f64,
+    /// Time in seconds to reach the pitch of the sound
+    pub duration: f64,
+    /// How the pitch moves during the glide
+    pub interpolation: PitchInterpolation,
+}
+
+/// Pitches frequencies can be snapped to
+#[derive(Clone)]
+pub enum Scale {
+    /// Every equal-tempered note, with A4 at A4_FREQUENCY
+    EqualTempered,
+    /// Any set of frequencies in Hertz, like the tuning table of a particular piece of hardware
+    Frequencies(Vec<f64>),
+}
+
+impl Scale {
+    /// Gives the pitch of the scale closest to a frequency in cents, None if there is none
+    pub fn nearest(&self, frequency: f64) -> Option<f64> {
+        match self {
+            Scale::EqualTempered => {
+                let note_number = (f64::from(A4_NOTE_NUMBER)
+                    + cents_between(A4_FREQUENCY, frequency) / 100f64)
+                    .round();
+                if !note_number.is_finite() {
+                    return None;
+                }
+                Some(note_number_to_frequency(note_number as i32))
+            }
+            Scale::Frequencies(frequencies) => frequencies
+                .iter()
+                .cloned()
+                .filter(|f| f.is_normal() & (*f > 0f64))
+                .min_by(|a, b| {
+                    cents_between(frequency, *a)
+                        .abs()
+                        .total_cmp(&cents_between(frequency, *b).abs())
+                }),
+        }
+    }
+}
+
+/// Moves a frequency to the nearest pitch of a scale if it is at most a tolerance in cents away.
+/// Gives the frequency to use along with the detune in cents from it back to the original frequency, which is 0 if it was not moved.
+pub fn snap_to_scale(frequency: f64, scale: &Scale, tolerance: f64) -> (f64, f64) {
+    match scale.nearest(frequency) {
+        Some(pitch) => {
+            let detune = cents_between(pitch, frequency);
+            if detune.abs() <= tolerance {
+                (pitch, detune)
+            } else {
+                (frequency, 0f64)
+            }
+        }
+        None => (frequency, 0f64),
+    }
+}
+
+/// Interval between two frequencies in cents, negative if going down
+pub fn cents_between(from: f64, to: f64) -> f64 {
+    CENTS_PER_OCTAVE * (to / from).log2()
+}
+
+/// Moves a frequency by an amount of cents
+pub fn shift_by_cents(frequency: f64, cents: f64) -> f64 {
+    frequency * (cents / CENTS_PER_OCTAVE).exp2()
+}
+
+/// Gives the equal-tempered frequency of a MIDI note number, with A4 at 440 Hz
+pub fn note_number_to_frequency(note_number: i32) -> f64 {
+    A4_FREQUENCY * (f64::from(note_number - A4_NOTE_NUMBER) / 12f64).exp2()
+}
+
+/// Gives the name of the note at a frequency, like "A4" or "C#-1".
+/// Returns None if the frequency is not an equal-tempered note.
+pub fn note_name(frequency: f64) -> Option<String> {
+    let note_number = (f64::from(A4_NOTE_NUMBER)
+        + cents_between(A4_FREQUENCY, frequency) / 100f64)
+        .round();
+    if !note_number.is_finite() {
+        return None;
+    }
+    let note_number = note_number as i32;
+    if cents_between(note_number_to_frequency(note_number), frequency).abs() > NOTE_NAME_TOLERANCE {
+        return None;
+    }
+    Some(format!(
+        "{}{}",
+        NOTE_NAMES[note_number.rem_euclid(12) as usize],
+        note_number.div_euclid(12) - 1
+    ))
+}
+
+/// Gives the frequency of a note name like "A4", "Bb3" or "C#-1".
+/// Returns None if the name cannot be read.
+pub fn parse_note_name(name: &str) -> Option<f64> {
+    let mut chars = name.chars();
+    let mut semitone = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let mut rest = chars.as_str();
+    if let Some(r) = rest.strip_prefix('#') {
+        semitone += 1;
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('b') {
+        semitone -= 1;
+        rest = r;
+    }
+    let octave: i32 = rest.parse().ok()?;
+    Some(note_number_to_frequency((octave + 1) * 12 + semitone))
+}
+
+/// Interpolates between two frequencies linearly in Hertz
+pub fn interpolate_hertz(from: f64, to: f64, position: f64) -> f64 {
+    from + (to - from) * position
+}
+
+/// Interpolates between two frequencies linearly in cents
+pub fn interpolate_cents(from: f64, to: f64, position: f64) -> f64 {
+    shift_by_cents(from, cents_between(from, to) * position)
+}
+
+/// Reads frames at a different speed with linear interpolation, changing both pitch and length.
+/// A step of 2 plays the frames twice as fast, an octave higher.
+/// # Arguments
+/// * frames: Frames to read
+/// * step: How many input frames to move forward for each output frame, finite and strictly positive
+/// * nb_channels: Amount of channels in the output, input channels are reused if there are not enough
+pub fn resample_frames(frames: &[Frame], step: f64, nb_channels: usize) -> Result<Vec<Frame>> {
+    resample_frames_gliding(
+        frames,
+        step,
+        step,
+        0,
+        PitchInterpolation::Cents,
+        nb_channels,
+    )
+}
+
+/// Reads frames with linear interpolation at a speed moving from one step to another, for pitch bends.
+/// Steps are ratios of frequencies, so interpolating them in cents gives an even pitch motion.
+/// # Arguments
+/// * frames: Frames to read
+/// * from_step: Step of the first output frame, finite and strictly positive
+/// * to_step: Step reached at the end of the glide and kept after it, finite and strictly positive
+/// * glide_length: Amount of output frames the step takes to go from from_step to to_step
+/// * interpolation: How the step moves during the glide
+/// * nb_channels: Amount of channels in the output, input channels are reused if there are not enough
+pub fn resample_frames_gliding(
+    frames: &[Frame],
+    from_step: f64,
+    to_step: f64,
+    glide_length: usize,
+    interpolation: PitchInterpolation,
+    nb_channels: usize,
+) -> Result<Vec<Frame>> {
+    for step in &[from_step, to_step] {
+        if !step.is_finite() | (*step <= 0f64) {
+            return Err(SequencerError::InvalidResampleStep(*step));
+        }
+    }
+    let mut out = Vec::new();
+    if frames.is_empty() {
+        return Ok(out);
+    }
+    let last = frames.len() - 1;
+    let mut position = 0f64;
+    while position <= last as f64 {
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        let current = &frames[index];
+        let next = &frames[(index + 1).min(last)];
+        let mut samples = Vec::with_capacity(nb_channels);
+        for channel in 0..nb_channels {
+            let input_channel = channel.min(current.samples.len().saturating_sub(1));
+            let s1 = match current.samples.get(input_channel) {
+                Some(Sample::Float(s)) => *s,
+                Some(_) => return Err(SequencerError::UnsupportedSampleType),
+                None => 0f32,
+            };
+            let s2 = match next.samples.get(input_channel) {
+                Some(Sample::Float(s)) => *s,
+                Some(_) => return Err(SequencerError::UnsupportedSampleType),
+                None => s1,
+            };
+            samples.push(Sample::Float(s1 + (s2 - s1) * fraction));
+        }
+        position += if out.len() < glide_length {
+            interpolation.interpolate(
+                from_step,
+                to_step,
+                out.len() as f64 / glide_length as f64,
+            )
+        } else {
+            to_step
+        };
+        out.push(Frame { samples });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest difference allowed between two frequencies in the tests, in Hertz
+    const EPSILON: f64 = 1e-9;
+
+    #[test]
+    fn interpolate_hertz_endpoints_and_midpoint() {
+        assert!((interpolate_hertz(220f64, 880f64, 0f64) - 220f64).abs() < EPSILON);
+        assert!((interpolate_hertz(220f64, 880f64, 1f64) - 880f64).abs() < EPSILON);
+        assert!((interpolate_hertz(220f64, 880f64, 0.5f64) - 550f64).abs() < EPSILON);
+    }
+
+    #[test]
+    fn interpolate_cents_endpoints_and_midpoint() {
+        assert!((interpolate_cents(220f64, 880f64, 0f64) - 220f64).abs() < EPSILON);
+        assert!((interpolate_cents(220f64, 880f64, 1f64) - 880f64).abs() < EPSILON);
+        // Halfway between two octaves is one octave
+        assert!((interpolate_cents(220f64, 880f64, 0.5f64) - 440f64).abs() < EPSILON);
+        assert!((interpolate_cents(880f64, 220f64, 0.5f64) - 440f64).abs() < EPSILON);
+    }
+
+    #[test]
+    fn interpolation_modes_match_their_functions() {
+        assert_eq!(
+            PitchInterpolation::Hertz.interpolate(100f64, 300f64, 0.25f64),
+            interpolate_hertz(100f64, 300f64, 0.25f64)
+        );
+        assert_eq!(
+            PitchInterpolation::Cents.interpolate(100f64, 300f64, 0.25f64),
+            interpolate_cents(100f64, 300f64, 0.25f64)
+        );
+    }
+
+    #[test]
+    fn resample_rejects_invalid_steps() {
+        let frames = vec![
+            Frame {
+                samples: vec![Sample::Float(0f32)],
+            };
+            4
+        ];
+        for step in &[0f64, -1f64, ::std::f64::NAN, ::std::f64::INFINITY] {
+            assert!(resample_frames(&frames, *step, 1).is_err());
+        }
+        assert_eq!(resample_frames(&frames, 2f64, 1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resample_fills_frames_without_samples() {
+        let frames = vec![Frame { samples: vec![] }; 2];
+        let out = resample_frames(&frames, 1f64, 2).unwrap();
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].samples.len(), 2);
+    }
+}
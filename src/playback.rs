@@ -0,0 +1,126 @@
+use pcm::{Frame, Sample};
+use pitch;
+use std::f64::consts::PI;
+use Result;
+
+/// Length of the grains used for changing the speed without changing the pitch, in seconds
+const GRAIN_DURATION: f64 = 0.05f64;
+
+/// Tape-style changes of how the sound of an Instrument is read, applied to its keys before the envelope
+#[derive(Clone)]
+pub struct PlaybackModifier {
+    /// Plays the keys backwards, from their end to their start
+    pub reverse: bool,
+    /// How fast keys are played, 0.5 plays them at half speed and 2 at double speed. A negative speed also plays them backwards.
+    pub speed: f64,
+    /// What the speed changes
+    pub speed_mode: SpeedMode,
+}
+
+/// What the speed of a PlaybackModifier changes
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpeedMode {
+    /// Both the pitch and the time, like a tape played slower or faster. Half speed is an octave lower.
+    PitchAndTime,
+    /// Only the time, the pitch stays the same. Done by overlapping short grains, which can sound slightly rough.
+    TimeOnly,
+}
+
+impl PlaybackModifier {
+    /// Gives the frames of a key as read with this modifier. Only Float samples are processed.
+    /// # Arguments
+    /// * frames: Frames of the key, from where the sound starts
+    /// * sample_rate: Sample rate of the key
+    /// * nb_channels: Amount of channels of the key
+    pub fn apply(
+        &self,
+        frames: &[Frame],
+        sample_rate: u32,
+        nb_channels: usize,
+    ) -> Result<Vec<Frame>> {
+        let mut frames = frames.to_vec();
+        if self.reverse ^ (self.speed < 0f64) {
+            frames.reverse();
+        }
+        let speed = self.speed.abs();
+        if !speed.is_normal() | (speed == 1f64) {
+            return Ok(frames);
+        }
+        match self.speed_mode {
+            SpeedMode::PitchAndTime => pitch::resample_frames(&frames, speed, nb_channels),
+            SpeedMode::TimeOnly => Ok(stretch(&frames, speed, sample_rate, nb_channels)),
+        }
+    }
+    /// How much of a key is read for each second of sound, to know how long keys need to be
+    pub fn source_rate(&self) -> f64 {
+        if self.speed.is_normal() {
+            self.speed.abs()
+        } else {
+            1f64
+        }
+    }
+}
+
+/// Changes the speed of frames without changing their pitch, by adding windowed grains read at the speed
+/// but placed at the original rate. Overlapping windows are normalized so the level does not change.
+fn stretch(frames: &[Frame], speed: f64, sample_rate: u32, nb_channels: usize) -> Vec<Frame> {
+    let grain = ((GRAIN_DURATION * f64::from(sample_rate)) as usize).max(2);
+    let hop_out = grain / 2;
+    let hop_in = hop_out as f64 * speed;
+    let nb_out = (frames.len() as f64 / speed) as usize;
+    let window: Vec<f32> = (0..grain)
+        .map(|i| (0.5f64 - 0.5f64 * (2f64 * PI * i as f64 / grain as f64).cos()) as f32)
+        .collect();
+    let mut samples = vec![0f32; nb_out * nb_channels];
+    let mut weights = vec![0f32; nb_out];
+    let mut grain_id = 0usize;
+    while grain_id * hop_out < nb_out {
+        let out_start = grain_id * hop_out;
+        let in_start = (grain_id as f64 * hop_in) as usize;
+        for (i, w) in window.iter().enumerate() {
+            let (out_id, in_id) = (out_start + i, in_start + i);
+            if (out_id >= nb_out) | (in_id >= frames.len()) {
+                break;
+            }
+            weights[out_id] += w;
+            let input = &frames[in_id].samples;
+            for channel in 0..nb_channels {
+                // Frames without samples are silent
+                let input_channel = channel.min(input.len().saturating_sub(1));
+                if let Some(Sample::Float(s)) = input.get(input_channel) {
+                    samples[out_id * nb_channels + channel] += s * w;
+                }
+            }
+        }
+        grain_id += 1;
+    }
+    samples
+        .chunks(nb_channels.max(1))
+        .zip(weights)
+        .map(|(frame, weight)| Frame {
+            samples: frame
+                .iter()
+                .map(|s| Sample::Float(if weight > 0f32 { s / weight } else { 0f32 }))
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stretch_makes_frames_without_samples_silent() {
+        let mut frames = vec![
+            Frame {
+                samples: vec![Sample::Float(0.5f32)],
+            };
+            100
+        ];
+        frames[50].samples.clear();
+        let out = stretch(&frames, 0.5f64, 1_000, 2);
+        assert_eq!(out.len(), 200);
+        assert!(out.iter().all(|f| f.samples.len() == 2));
+    }
+}
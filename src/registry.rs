@@ -0,0 +1,224 @@
+use envelopes::AdsrEnvelope;
+use error::SequencerError;
+#[cfg(feature = "scripting")]
+use scripting::{ScriptedEnvelope, ScriptedGenerator};
+use std::collections::HashMap;
+#[cfg(feature = "tone_generators")]
+use tone_generators::{
+    HardSyncGenerator, LfsrNoiseGenerator, PulseWaveGenerator, RingModulationGenerator,
+    SineWaveGenerator, SquareWaveGenerator,
+};
+use {Envelope, KeyGenerator, Result};
+
+/// Creates a Key Generator from its parameters
+pub type GeneratorFactory = Box<dyn Fn(&Parameters) -> Result<Box<dyn KeyGenerator>>>;
+/// Creates an Envelope from its parameters
+pub type EnvelopeFactory = Box<dyn Fn(&Parameters) -> Result<Box<dyn Envelope>>>;
+
+/// A value of a parameter given to a factory
+#[derive(Clone, Debug)]
+pub enum ParameterValue {
+    /// A whole number
+    Integer(i64),
+    /// A number that may have a fractional part
+    Float(f64),
+    /// True or false
+    Boolean(bool),
+    /// Text
+    Text(String),
+}
+
+/// Named parameters given to a factory, read from a file or given by a user
+#[derive(Clone, Debug, Default)]
+pub struct Parameters {
+    /// Values of the parameters by name
+    pub values: HashMap<String, ParameterValue>,
+}
+
+impl Parameters {
+    /// Creates an empty set of parameters
+    pub fn new() -> Parameters {
+        Parameters {
+            values: HashMap::new(),
+        }
+    }
+    /// Sets a parameter, replacing any previous value
+    pub fn set(&mut self, name: &str, value: ParameterValue) {
+        self.values.insert(name.to_string(), value);
+    }
+    /// Reads a number, integers are accepted
+    pub fn get_f64(&self, name: &str) -> Result<f64> {
+        match self.values.get(name) {
+            Some(ParameterValue::Float(f)) => Ok(*f),
+            Some(ParameterValue::Integer(i)) => Ok(*i as f64),
+            _ => Err(invalid(name, "a number")),
+        }
+    }
+    /// Reads a positive integer that fits in 32 bits
+    pub fn get_u32(&self, name: &str) -> Result<u32> {
+        match self.values.get(name) {
+            Some(ParameterValue::Integer(i)) if (0..=i64::from(u32::MAX)).contains(i) => {
+                Ok(*i as u32)
+            }
+            _ => Err(invalid(name, "a positive integer")),
+        }
+    }
+    /// Reads a boolean
+    pub fn get_bool(&self, name: &str) -> Result<bool> {
+        match self.values.get(name) {
+            Some(ParameterValue::Boolean(b)) => Ok(*b),
+            _ => Err(invalid(name, "a boolean")),
+        }
+    }
+    /// Reads some text
+    pub fn get_str(&self, name: &str) -> Result<&str> {
+        match self.values.get(name) {
+            Some(ParameterValue::Text(t)) => Ok(t),
+            _ => Err(invalid(name, "text")),
+        }
+    }
+    /// Reads a list of numbers written as text, separated by spaces or commas
+    pub fn get_f64_list(&self, name: &str) -> Result<Vec<f64>> {
+        self.get_str(name)?
+            .split(|c: char| c.is_whitespace() | (c == ','))
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse().map_err(|_| invalid(name, "a list of numbers")))
+            .collect()
+    }
+}
+
+/// Error for a parameter that is missing or does not have the expected type
+fn invalid(name: &str, expected: &str) -> SequencerError {
+    SequencerError::InvalidParameter(format!("{} should be {}", name, expected))
+}
+
+/// Names Key Generators and Envelopes so that they can be created from files, including ones from other crates.
+/// The default Registry contains the Generators and Envelopes of this crate.
+pub struct Registry {
+    /// Factories of Key Generators by name
+    generators: HashMap<String, GeneratorFactory>,
+    /// Factories of Envelopes by name
+    envelopes: HashMap<String, EnvelopeFactory>,
+}
+
+impl Registry {
+    /// Creates a Registry without anything in it
+    pub fn empty() -> Registry {
+        Registry {
+            generators: HashMap::new(),
+            envelopes: HashMap::new(),
+        }
+    }
+    /// Adds a Key Generator, replacing any other one with the same name
+    pub fn register_generator(&mut self, name: &str, factory: GeneratorFactory) {
+        self.generators.insert(name.to_string(), factory);
+    }
+    /// Adds an Envelope, replacing any other one with the same name
+    pub fn register_envelope(&mut self, name: &str, factory: EnvelopeFactory) {
+        self.envelopes.insert(name.to_string(), factory);
+    }
+    /// Creates a Key Generator by name
+    pub fn create_generator(
+        &self,
+        name: &str,
+        parameters: &Parameters,
+    ) -> Result<Box<dyn KeyGenerator>> {
+        match self.generators.get(name) {
+            Some(f) => f(parameters),
+            None => Err(SequencerError::UnknownGenerator(name.to_string())),
+        }
+    }
+    /// Creates an Envelope by name
+    pub fn create_envelope(
+        &self,
+        name: &str,
+        parameters: &Parameters,
+    ) -> Result<Box<dyn Envelope>> {
+        match self.envelopes.get(name) {
+            Some(f) => f(parameters),
+            None => Err(SequencerError::UnknownEnvelope(name.to_string())),
+        }
+    }
+    /// Names of all Key Generators, sorted
+    pub fn generator_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.generators.keys().map(|n| n.as_str()).collect();
+        names.sort();
+        names
+    }
+    /// Names of all Envelopes, sorted
+    pub fn envelope_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.envelopes.keys().map(|n| n.as_str()).collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        let mut registry = Registry::empty();
+        #[cfg(feature = "tone_generators")]
+        {
+            registry
+                .register_generator("square", Box::new(|_| Ok(Box::new(SquareWaveGenerator {}))));
+            registry.register_generator("sine", Box::new(|_| Ok(Box::new(SineWaveGenerator {}))));
+            registry.register_generator(
+                "ring_modulation",
+                Box::new(|p| {
+                    Ok(Box::new(RingModulationGenerator {
+                        modulator_ratio: p.get_f64("modulator_ratio")?,
+                    }))
+                }),
+            );
+            registry.register_generator(
+                "hard_sync",
+                Box::new(|p| {
+                    Ok(Box::new(HardSyncGenerator {
+                        slave_ratio: p.get_f64("slave_ratio")?,
+                    }))
+                }),
+            );
+            registry.register_generator(
+                "pulse",
+                Box::new(|p| {
+                    Ok(Box::new(PulseWaveGenerator {
+                        duty_cycles: p.get_f64_list("duty_cycles")?,
+                        step_duration: p.get_f64("step_duration")?,
+                        loop_sequence: p.get_bool("loop_sequence")?,
+                    }))
+                }),
+            );
+            registry.register_generator(
+                "lfsr_noise",
+                Box::new(|p| {
+                    Ok(Box::new(LfsrNoiseGenerator {
+                        register_length: p.get_u32("register_length")?,
+                        tap: p.get_u32("tap")?,
+                    }))
+                }),
+            );
+        }
+        #[cfg(feature = "scripting")]
+        {
+            registry.register_generator(
+                "script",
+                Box::new(|p| Ok(Box::new(ScriptedGenerator::new(p.get_str("source")?)?))),
+            );
+            registry.register_envelope(
+                "script",
+                Box::new(|p| Ok(Box::new(ScriptedEnvelope::new(p.get_str("source")?)?))),
+            );
+        }
+        registry.register_envelope(
+            "adsr",
+            Box::new(|p| {
+                Ok(Box::new(AdsrEnvelope {
+                    attack: p.get_f64("attack")?,
+                    decay: p.get_f64("decay")?,
+                    sustain: p.get_f64("sustain")?,
+                    release: p.get_f64("release")?,
+                }))
+            }),
+        );
+        registry
+    }
+}
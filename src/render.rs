@@ -0,0 +1,268 @@
+use analysis;
+use error::SequencerError;
+use pcm::{Frame, Sample, PCM};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::mem;
+use std::time::{Duration, Instant};
+use {duration_to_frames, FrequencyId, InstrumentId, Result};
+
+/// Frequency of the debug beep in Hertz
+const BEEP_FREQUENCY: f64 = 1760f64;
+/// Amplitude of the debug beep
+const BEEP_AMPLITUDE: f64 = 0.25f64;
+/// Longest time a debug beep plays for, in seconds
+const BEEP_DURATION: f64 = 0.25f64;
+/// Rate at which the debug beep is switched on and off, in Hertz
+const BEEP_CHOP_RATE: f64 = 16f64;
+
+/// Changes how MusicSequencer::render_with_options renders
+#[derive(Clone, Default)]
+pub struct RenderOptions {
+    /// Replaces notes that cannot be played with a beep instead of stopping the render, to hear where problems are.
+    /// The notes are logged in the skipped notes of the RenderReport, nothing is printed.
+    pub debug_beeps: bool,
+    /// Measures the sound of each Instrument on its own, as mixed in the output, and puts the results in the RenderReport
+    pub stem_stats: bool,
+    /// Measures the time spent in each stage of the render, in total and for each Instrument, and puts it in the RenderReport
+    pub profile: bool,
+    /// Stops the render with an error when it takes too long or would give too many frames
+    pub limits: RenderLimits,
+}
+
+/// Limits protecting batch renders from malformed Sequences that would otherwise render for hours.
+/// When one is exceeded, the render stops with a RenderLimitExceeded error holding the report of what was done until then.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderLimits {
+    /// Longest wall-clock time the render can take, checked between Instruments while generating keys and between notes while mixing.
+    /// It is not checked while a single Instrument generates its keys or a single note is played, so the render can go past it by that much.
+    /// If not set, the render can take any time.
+    pub max_time: Option<Duration>,
+    /// Most frames the output can have, checked before mixing anything. If not set, only MAX_SONG_LENGTH limits the output.
+    pub max_frames: Option<usize>,
+}
+
+/// A limit a render went past
+#[derive(Clone, Copy, Debug)]
+pub enum RenderLimit {
+    /// The render took longer than this time
+    Time(Duration),
+    /// The output would have been longer than this amount of frames
+    Frames(usize),
+}
+
+/// Checks the RenderLimits of a render as it goes
+#[derive(Clone, Copy, Debug)]
+pub struct Watchdog {
+    /// The limits to check
+    pub limits: RenderLimits,
+    /// When the render started
+    pub started: Instant,
+}
+
+/// What a render will give, known before rendering anything, so that hosts can preallocate buffers, estimate render times
+/// or reject renders that are too big
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputSpec {
+    /// Exact amount of frames of the rendered PCM
+    pub nb_frames: usize,
+    /// Amount of channels of each frame
+    pub nb_channels: u16,
+    /// Sample rate of the rendered PCM
+    pub sample_rate: u32,
+    /// Duration in seconds, from the start to the end of the release of the last note
+    pub duration: f64,
+    /// Estimated memory taken by the rendered PCM in bytes. Keys and the sound of notes being mixed are not counted.
+    pub memory: usize,
+}
+
+impl OutputSpec {
+    /// Gives the spec of a render from its parameters and duration
+    pub fn new(nb_channels: u16, sample_rate: u32, duration: f64) -> Result<OutputSpec> {
+        let nb_frames = duration_to_frames(duration, sample_rate)?;
+        let frame_size =
+            mem::size_of::<Frame>() + usize::from(nb_channels) * mem::size_of::<Sample>();
+        Ok(OutputSpec {
+            nb_frames,
+            nb_channels,
+            sample_rate,
+            duration,
+            memory: nb_frames.saturating_mul(frame_size),
+        })
+    }
+    /// Amount of samples of all channels, the length of a buffer holding the render interleaved
+    pub fn nb_samples(&self) -> usize {
+        self.nb_frames.saturating_mul(usize::from(self.nb_channels))
+    }
+}
+
+/// What happened during a render
+#[derive(Clone, Debug, Default)]
+pub struct RenderReport {
+    /// Notes that could not be played, in the order of the Sequence sorted by time
+    pub skipped_notes: Vec<SkippedNote>,
+    /// Measurements of the sound of each Instrument, sorted by Instrument ID. Empty unless asked for in the RenderOptions.
+    /// Layered Instruments are measured through the Instruments of their layers.
+    pub stems: Vec<StemStats>,
+    /// Time spent in each stage of the render, None unless asked for in the RenderOptions
+    pub profile: Option<RenderProfile>,
+}
+
+/// A note that could not be played
+#[derive(Clone, Debug)]
+pub struct SkippedNote {
+    /// Time at which the note starts
+    pub start_at: f64,
+    /// Time at which the note stops
+    pub end_at: f64,
+    /// Instrument of the note
+    pub instrument_id: InstrumentId,
+    /// Frequency ID of the note
+    pub frequency_id: FrequencyId,
+    /// Why the note could not be played
+    pub reason: String,
+}
+
+/// Measurements of the sound of one Instrument in a render, for balancing mixes
+#[derive(Clone, Debug)]
+pub struct StemStats {
+    /// The measured Instrument
+    pub instrument_id: InstrumentId,
+    /// Integrated loudness in LUFS, negative infinity for silence
+    pub loudness: f64,
+    /// Highest absolute sample value
+    pub peak: f32,
+    /// Ratio between the peak and the root mean square in dB
+    pub crest_factor: f64,
+    /// Center of mass of the spectrum in Hertz, higher for brighter sounds
+    pub spectral_centroid: f64,
+}
+
+impl StemStats {
+    /// Measures the sound of an Instrument
+    pub fn measure(instrument_id: InstrumentId, stem: &PCM) -> Result<StemStats> {
+        Ok(StemStats {
+            instrument_id,
+            loudness: analysis::integrated_loudness(stem)?,
+            peak: analysis::peak(stem)?,
+            crest_factor: analysis::crest_factor(stem)?,
+            spectral_centroid: analysis::spectral_centroid(stem)?,
+        })
+    }
+}
+
+/// Time spent in each stage of rendering
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTimes {
+    /// Generating the keys
+    pub key_generation: Duration,
+    /// Making the sound of notes from the keys, with their envelope
+    pub synthesis: Duration,
+    /// Adding the sound of notes to the output
+    pub mixing: Duration,
+}
+
+impl StageTimes {
+    /// Time spent in all stages
+    pub fn total(&self) -> Duration {
+        self.key_generation + self.synthesis + self.mixing
+    }
+    /// Adds the times of other stages to these ones
+    fn add(&mut self, other: &StageTimes) {
+        self.key_generation += other.key_generation;
+        self.synthesis += other.synthesis;
+        self.mixing += other.mixing;
+    }
+}
+
+/// Time spent in a render by one Instrument
+#[derive(Clone, Debug)]
+pub struct InstrumentProfile {
+    /// The measured Instrument
+    pub instrument_id: InstrumentId,
+    /// Time spent in each stage for the notes of the Instrument
+    pub times: StageTimes,
+}
+
+/// Where the time of a render went, to find which Instrument makes it slow.
+/// Layered Instruments are measured through the Instruments of their layers, frozen ones only take mixing time.
+#[derive(Clone, Debug, Default)]
+pub struct RenderProfile {
+    /// Time spent in each stage for all Instruments
+    pub total: StageTimes,
+    /// Time spent by each Instrument, sorted by Instrument ID
+    pub instruments: Vec<InstrumentProfile>,
+}
+
+impl RenderProfile {
+    /// Gathers the times measured for each Instrument
+    pub fn from_times(times: HashMap<InstrumentId, StageTimes>) -> RenderProfile {
+        let mut profile = RenderProfile::default();
+        for (instrument_id, times) in times {
+            profile.total.add(&times);
+            profile.instruments.push(InstrumentProfile {
+                instrument_id,
+                times,
+            });
+        }
+        profile.instruments.sort_by_key(|i| i.instrument_id);
+        profile
+    }
+}
+
+impl Watchdog {
+    /// Starts watching a render now
+    pub fn new(limits: RenderLimits) -> Watchdog {
+        Watchdog {
+            limits,
+            started: Instant::now(),
+        }
+    }
+    /// Gives an error if the render has been running for longer than allowed
+    pub fn check_time(&self) -> Result<()> {
+        match self.limits.max_time {
+            Some(max) if self.started.elapsed() > max => Err(exceeded(RenderLimit::Time(max))),
+            _ => Ok(()),
+        }
+    }
+    /// Gives an error if an amount of frames is more than allowed
+    pub fn check_frames(&self, nb_frames: usize) -> Result<()> {
+        match self.limits.max_frames {
+            Some(max) if nb_frames > max => Err(exceeded(RenderLimit::Frames(max))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Error for an exceeded limit, with an empty report filled in by render_with_options
+fn exceeded(limit: RenderLimit) -> SequencerError {
+    SequencerError::RenderLimitExceeded(limit, Box::default())
+}
+
+/// Generates the debug beep replacing a note, a chopped square wave no instrument is likely to sound like.
+/// Durations that are not positive give a full beep, so that empty or reversed notes are still heard.
+pub fn beep(sample_rate: u32, nb_channels: usize, duration: f64) -> Vec<Frame> {
+    let sample_rate = f64::from(sample_rate);
+    let duration = if duration > 0f64 {
+        duration.min(BEEP_DURATION)
+    } else {
+        BEEP_DURATION
+    };
+    let nb_frames = (duration * sample_rate) as usize;
+    (0..nb_frames)
+        .map(|frame_id| {
+            let time = frame_id as f64 / sample_rate;
+            let chop = (2f64 * PI * BEEP_CHOP_RATE * time).sin() >= 0f64;
+            let value = if !chop {
+                0f64
+            } else if (2f64 * PI * BEEP_FREQUENCY * time).sin() >= 0f64 {
+                BEEP_AMPLITUDE
+            } else {
+                -BEEP_AMPLITUDE
+            };
+            Frame {
+                samples: vec![Sample::Float(value as f32); nb_channels],
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,168 @@
+use error::SequencerError;
+use pcm::{Frame, PCMParameters, Sample, PCM};
+use rhai::{Dynamic, Engine, Scope, AST};
+use {duration_to_frames, Envelope, Key, KeyGenerator, Result};
+
+/// Most operations a single call to a script function can do before being stopped, so that scripts cannot hang the render
+const MAX_OPERATIONS: u64 = 100_000;
+/// Deepest a script can nest function calls
+const MAX_CALL_LEVELS: usize = 32;
+/// Longest string a script can build
+const MAX_STRING_SIZE: usize = 1_024;
+/// Largest array or map a script can build
+const MAX_COLLECTION_SIZE: usize = 1_024;
+
+/// Creates an Engine without access to anything outside of the script and with limits on what a script can use.
+/// `print` and `debug` do nothing, so that scripts cannot write to the output of the program.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|_| {});
+    engine.on_debug(|_, _, _| {});
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_COLLECTION_SIZE);
+    engine.set_max_map_size(MAX_COLLECTION_SIZE);
+    engine
+}
+
+/// A compiled script along with the Engine running it
+struct Script {
+    /// Engine running the script
+    engine: Engine,
+    /// The compiled script
+    ast: AST,
+}
+
+impl Script {
+    /// Compiles a script
+    fn compile(source: &str) -> Result<Script> {
+        let engine = sandboxed_engine();
+        let ast = engine
+            .compile(source)
+            .map_err(|e| SequencerError::ScriptError(e.to_string()))?;
+        Ok(Script { engine, ast })
+    }
+    /// Does the script define a function with this name ?
+    fn has_function(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+    /// Calls a function of the script with floats and gives its result as a float. Integers are converted.
+    fn call(&self, name: &str, args: Vec<f64>) -> Result<f64> {
+        let args: Vec<Dynamic> = args.into_iter().map(Dynamic::from_float).collect();
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, name, args)
+            .map_err(|e| SequencerError::ScriptError(e.to_string()))?;
+        if let Ok(f) = result.as_float() {
+            return Ok(f);
+        }
+        match result.as_int() {
+            Ok(i) => Ok(i as f64),
+            Err(t) => Err(SequencerError::ScriptError(format!(
+                "{} returned a {} instead of a number",
+                name, t
+            ))),
+        }
+    }
+}
+
+/// KeyGenerator defined by a script.
+/// The script has to define `fn sample(time, frequency)` giving the value of the waveform between -1 and 1 at a time in seconds.
+/// Values outside of [-1, 1] are clipped, and the key is silent wherever the script fails.
+/// The script is called for every sample, which is far slower than a native KeyGenerator: using a KeyCache is recommended.
+pub struct ScriptedGenerator {
+    /// The script generating the waveform
+    script: Script,
+    /// Source of the script, identifying the generator in a KeyCache
+    source: String,
+}
+
+impl ScriptedGenerator {
+    /// Compiles a script and checks that it gives a number for a first sample
+    pub fn new(source: &str) -> Result<ScriptedGenerator> {
+        let script = Script::compile(source)?;
+        script.call("sample", vec![0f64, 440f64])?;
+        Ok(ScriptedGenerator {
+            script,
+            source: source.to_string(),
+        })
+    }
+}
+
+impl KeyGenerator for ScriptedGenerator {
+    fn cache_id(&self) -> Option<String> {
+        Some(format!("script {}", self.source))
+    }
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
+        let sample_rate = f64::from(parameters.sample_rate);
+        let nb_frames = duration_to_frames(*duration, parameters.sample_rate).unwrap_or(0);
+        let frames = (0..nb_frames)
+            .map(|frame_id| {
+                let value = self.script
+                    .call("sample", vec![frame_id as f64 / sample_rate, *frequency])
+                    .unwrap_or(0f64)
+                    .clamp(-1f64, 1f64);
+                Frame {
+                    samples: vec![Sample::Float(value as f32); parameters.nb_channels as usize],
+                }
+            })
+            .collect();
+        Key {
+            audio: PCM {
+                parameters: parameters.clone(),
+                loop_info: None,
+                frames,
+            },
+            frequency: *frequency,
+        }
+    }
+}
+
+/// Envelope defined by a script.
+/// The script has to define `fn before_during_sustain(time)` and `fn after_sustain(time)` giving amplitudes between 0 and 1,
+/// and can define `fn release_duration()` giving the length of the release in seconds.
+/// Amplitudes outside of [0, 1] are clipped, and the amplitude is 0 wherever the script fails.
+/// The script is called for every sample of every note, which is far slower than a native Envelope.
+pub struct ScriptedEnvelope {
+    /// The script giving the amplitudes
+    script: Script,
+    /// Length of the release given by the script, 0 if it does not define one
+    release_duration: f64,
+}
+
+impl ScriptedEnvelope {
+    /// Compiles a script and checks that it gives numbers for the start of the envelope and of the release
+    pub fn new(source: &str) -> Result<ScriptedEnvelope> {
+        let script = Script::compile(source)?;
+        script.call("before_during_sustain", vec![0f64])?;
+        script.call("after_sustain", vec![0f64])?;
+        let release_duration = if script.has_function("release_duration") {
+            script.call("release_duration", vec![])?.max(0f64)
+        } else {
+            0f64
+        };
+        Ok(ScriptedEnvelope {
+            script,
+            release_duration,
+        })
+    }
+}
+
+impl Envelope for ScriptedEnvelope {
+    fn before_during_sustain(&self, time: &f64) -> f64 {
+        self.script
+            .call("before_during_sustain", vec![*time])
+            .unwrap_or(0f64)
+            .clamp(0f64, 1f64)
+    }
+    fn after_sustain(&self, time: &f64) -> f64 {
+        self.script
+            .call("after_sustain", vec![*time])
+            .unwrap_or(0f64)
+            .clamp(0f64, 1f64)
+    }
+    fn release_duration(&self) -> f64 {
+        self.release_duration
+    }
+}
@@ -0,0 +1,322 @@
+use error::SequencerError;
+use std::io::Write;
+use {duration_to_frames, time_to_frame, Result};
+
+/// Beats per minute used when a TempoMap has no tempo change
+pub const DEFAULT_BPM: f64 = 120f64;
+/// Time signature used before the first time signature change
+pub const DEFAULT_TIME_SIGNATURE: TimeSignature = TimeSignature {
+    numerator: 4,
+    denominator: 4,
+};
+/// First line of a beat grid, naming the columns
+const BEAT_GRID_HEADER: &str = "# frame time bar beat song_position";
+/// MIDI beats, the unit of Song Position Pointers, in a beat (quarter note)
+const MIDI_BEATS_PER_BEAT: f64 = 4f64;
+
+/// Tempo and time signatures of a song over time, for converting between bars, beats and seconds.
+/// A beat is a quarter note, and bars are counted from 0.
+#[derive(Clone, Default)]
+pub struct TempoMap {
+    /// Tempo changes, sorted by beat. The tempo of the first change is also used before it.
+    pub changes: Vec<TempoChange>,
+    /// Time signature changes, sorted by bar. Bars before the first change are in 4/4.
+    pub time_signatures: Vec<TimeSignatureChange>,
+}
+
+/// How many notes of which length make a bar
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeSignature {
+    /// Amount of notes in a bar, the 3 of 3/4
+    pub numerator: u32,
+    /// Length of these notes as a fraction of a whole note, the 4 of 3/4
+    pub denominator: u32,
+}
+
+/// Change of time signature at the start of a bar
+#[derive(Clone)]
+pub struct TimeSignatureChange {
+    /// Bar starting with the new time signature
+    pub at_bar: u32,
+    /// The new time signature
+    pub signature: TimeSignature,
+}
+
+impl TimeSignature {
+    /// Length of a bar in beats (quarter notes)
+    pub fn beats_per_bar(self) -> f64 {
+        f64::from(self.numerator) * 4f64 / f64::from(self.denominator)
+    }
+}
+
+/// Change of tempo at a particular beat
+#[derive(Clone)]
+pub struct TempoChange {
+    /// Beat at which the tempo changes
+    pub at_beat: f64,
+    /// New tempo in beats per minute
+    pub bpm: f64,
+}
+
+/// A bar or beat boundary placed in rendered audio, for syncing pictures or visualizations to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timecode {
+    /// Frame of the rendered audio at which the boundary is, rounded to the nearest frame
+    pub frame: usize,
+    /// Time of the boundary in seconds
+    pub time: f64,
+    /// Bar, counted from 0
+    pub bar: u32,
+    /// Beat inside the bar counted from 0, in notes of the time signature (eighth notes in 6/8). Beat 0 is the start of the bar.
+    pub beat: u32,
+    /// Position from the start in sixteenth notes, as sent in MIDI Song Position Pointer messages
+    pub song_position: u32,
+}
+
+/// Goes through every bar and beat boundary of a TempoMap in order, up to a duration
+pub struct Timecodes<'a> {
+    /// Tempo and time signatures giving the boundaries
+    tempo_map: &'a TempoMap,
+    /// Sample rate of the audio the boundaries are placed in
+    sample_rate: u32,
+    /// Time in seconds at which the boundaries stop, not included
+    end: f64,
+    /// Bar of the next boundary
+    bar: u32,
+    /// Beat of the next boundary
+    beat: u32,
+    /// Time of the last boundary given, boundaries stop if time does not go forward
+    last_time: f64,
+}
+
+impl<'a> Iterator for Timecodes<'a> {
+    type Item = Timecode;
+    fn next(&mut self) -> Option<Timecode> {
+        let signature = self.tempo_map.time_signature_at_bar(self.bar);
+        let beat_length = 4f64 / f64::from(signature.denominator);
+        let beats =
+            self.tempo_map.bars_to_beats(f64::from(self.bar)) + f64::from(self.beat) * beat_length;
+        let time = self.tempo_map.beats_to_seconds(beats);
+        // Empty bars or a stopped tempo would give the same boundary forever
+        if time.is_nan() | (time >= self.end) | (time <= self.last_time) {
+            return None;
+        }
+        self.last_time = time;
+        let timecode = Timecode {
+            frame: time_to_frame(time, self.sample_rate).ok()?,
+            time,
+            bar: self.bar,
+            beat: self.beat,
+            song_position: (beats * MIDI_BEATS_PER_BEAT).round() as u32,
+        };
+        self.beat += 1;
+        if self.beat >= signature.numerator {
+            self.beat = 0;
+            self.bar = self.bar.checked_add(1)?;
+        }
+        Some(timecode)
+    }
+}
+
+/// A time that is either fixed or follows the tempo, used for tempo-synced modulation and delays
+#[derive(Clone, Copy)]
+pub enum SyncedTime {
+    /// Fixed time in seconds
+    Seconds(f64),
+    /// Time in beats, changing with the tempo
+    Beats(f64),
+}
+
+impl TempoMap {
+    /// Creates a TempoMap with a constant tempo
+    pub fn new(bpm: f64) -> TempoMap {
+        TempoMap {
+            changes: vec![TempoChange { at_beat: 0f64, bpm }],
+            time_signatures: Vec::new(),
+        }
+    }
+    /// Adds a time signature change at the start of a bar, replacing any other change at the same bar and keeping changes sorted.
+    /// Gives an error if the numerator or the denominator of the signature is 0.
+    pub fn add_time_signature(&mut self, at_bar: u32, signature: TimeSignature) -> Result<()> {
+        if (signature.numerator == 0) | (signature.denominator == 0) {
+            return Err(SequencerError::InvalidParameter(format!(
+                "time signature {}/{} should have a numerator and a denominator above 0",
+                signature.numerator, signature.denominator
+            )));
+        }
+        self.time_signatures.retain(|c| c.at_bar != at_bar);
+        let position = self.time_signatures
+            .iter()
+            .position(|c| c.at_bar > at_bar)
+            .unwrap_or(self.time_signatures.len());
+        self.time_signatures
+            .insert(position, TimeSignatureChange { at_bar, signature });
+        Ok(())
+    }
+    /// Gives the time signature of a bar
+    pub fn time_signature_at_bar(&self, bar: u32) -> TimeSignature {
+        self.time_signatures
+            .iter()
+            .take_while(|c| c.at_bar <= bar)
+            .last()
+            .map_or(DEFAULT_TIME_SIGNATURE, |c| c.signature)
+    }
+    /// Converts a position in bars to beats, the fractional part being a fraction of the bar
+    pub fn bars_to_beats(&self, bar: f64) -> f64 {
+        let mut beat = 0f64;
+        let mut bar_start = 0u32;
+        let mut signature = DEFAULT_TIME_SIGNATURE;
+        for change in &self.time_signatures {
+            if f64::from(change.at_bar) > bar {
+                break;
+            }
+            beat += f64::from(change.at_bar.saturating_sub(bar_start)) * signature.beats_per_bar();
+            bar_start = change.at_bar;
+            signature = change.signature;
+        }
+        beat + (bar - f64::from(bar_start)) * signature.beats_per_bar()
+    }
+    /// Converts a position in beats to bars, the fractional part being a fraction of the bar
+    pub fn beats_to_bars(&self, beat: f64) -> f64 {
+        let mut beat_start = 0f64;
+        let mut bar_start = 0u32;
+        let mut signature = DEFAULT_TIME_SIGNATURE;
+        for change in &self.time_signatures {
+            let change_beat = beat_start
+                + f64::from(change.at_bar.saturating_sub(bar_start)) * signature.beats_per_bar();
+            if change_beat > beat {
+                break;
+            }
+            beat_start = change_beat;
+            bar_start = change.at_bar;
+            signature = change.signature;
+        }
+        f64::from(bar_start) + (beat - beat_start) / signature.beats_per_bar()
+    }
+    /// Converts a bar and a beat inside of it to seconds
+    pub fn bar_beat_to_seconds(&self, bar: u32, beat: f64) -> f64 {
+        self.beats_to_seconds(self.bars_to_beats(f64::from(bar)) + beat)
+    }
+    /// Adds a tempo change, keeping changes sorted by beat
+    pub fn add_change(&mut self, at_beat: f64, bpm: f64) {
+        let position = self.changes
+            .iter()
+            .position(|c| c.at_beat > at_beat)
+            .unwrap_or(self.changes.len());
+        self.changes.insert(position, TempoChange { at_beat, bpm });
+    }
+    /// Gives the tempo in beats per minute at a beat
+    pub fn bpm_at_beat(&self, beat: f64) -> f64 {
+        let mut bpm = match self.changes.first() {
+            Some(c) => c.bpm,
+            None => return DEFAULT_BPM,
+        };
+        for change in &self.changes {
+            if change.at_beat > beat {
+                break;
+            }
+            bpm = change.bpm;
+        }
+        bpm
+    }
+    /// Gives the tempo in beats per minute at a time in seconds
+    pub fn bpm_at(&self, time: f64) -> f64 {
+        self.bpm_at_beat(self.seconds_to_beats(time))
+    }
+    /// Converts a position in beats to seconds
+    pub fn beats_to_seconds(&self, beat: f64) -> f64 {
+        let mut bpm = match self.changes.first() {
+            Some(c) => c.bpm,
+            None => DEFAULT_BPM,
+        };
+        let mut seconds = 0f64;
+        let mut previous_beat = 0f64;
+        for change in self.changes.iter().skip(1) {
+            if change.at_beat >= beat {
+                break;
+            }
+            seconds += (change.at_beat - previous_beat) * 60f64 / bpm;
+            previous_beat = change.at_beat;
+            bpm = change.bpm;
+        }
+        seconds + (beat - previous_beat) * 60f64 / bpm
+    }
+    /// Converts a position in seconds to beats
+    pub fn seconds_to_beats(&self, time: f64) -> f64 {
+        let mut bpm = match self.changes.first() {
+            Some(c) => c.bpm,
+            None => DEFAULT_BPM,
+        };
+        let mut seconds = 0f64;
+        let mut previous_beat = 0f64;
+        for change in self.changes.iter().skip(1) {
+            let change_time = seconds + (change.at_beat - previous_beat) * 60f64 / bpm;
+            if change_time >= time {
+                break;
+            }
+            seconds = change_time;
+            previous_beat = change.at_beat;
+            bpm = change.bpm;
+        }
+        previous_beat + (time - seconds) * bpm / 60f64
+    }
+    /// Converts a position in beats to a frame, rounded to the nearest frame
+    pub fn beats_to_frame(&self, beat: f64, sample_rate: u32) -> Result<usize> {
+        time_to_frame(self.beats_to_seconds(beat), sample_rate)
+    }
+    /// Gives every bar and beat boundary from the start up to a duration in seconds, placed in audio at a sample rate.
+    /// Boundaries stop early at a boundary that is not after the previous one, like with a 0/4 time signature.
+    pub fn timecodes(&self, sample_rate: u32, duration: f64) -> Result<Timecodes<'_>> {
+        duration_to_frames(duration, sample_rate)?;
+        Ok(Timecodes {
+            tempo_map: self,
+            sample_rate,
+            end: duration,
+            bar: 0,
+            beat: 0,
+            last_time: f64::NEG_INFINITY,
+        })
+    }
+    /// Writes every bar and beat boundary up to a duration as text, one per line with its frame, time, bar, beat and song position,
+    /// so that other tools can overlay rendered audio with a beat grid or drive sync systems from it. The first line names the columns.
+    /// # Arguments
+    /// * sample_rate: Sample rate of the rendered audio
+    /// * duration: Duration of the rendered audio in seconds
+    /// * writer: Where the text is written
+    pub fn write_beat_grid<W: Write>(
+        &self,
+        sample_rate: u32,
+        duration: f64,
+        writer: &mut W,
+    ) -> Result<()> {
+        writeln!(writer, "{}", BEAT_GRID_HEADER)?;
+        for timecode in self.timecodes(sample_rate, duration)? {
+            writeln!(
+                writer,
+                "{} {} {} {} {}",
+                timecode.frame, timecode.time, timecode.bar, timecode.beat, timecode.song_position
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl SyncedTime {
+    /// Gives the duration in seconds of this time when starting at a position in seconds
+    pub fn to_seconds_at(self, tempo_map: &TempoMap, at: f64) -> f64 {
+        match self {
+            SyncedTime::Seconds(s) => s,
+            SyncedTime::Beats(b) => {
+                tempo_map.beats_to_seconds(tempo_map.seconds_to_beats(at) + b) - at
+            }
+        }
+    }
+    /// Gives the same duration in frames when starting at a position in seconds, rounded to the nearest frame
+    pub fn to_frames_at(self, tempo_map: &TempoMap, at: f64, sample_rate: u32) -> Result<usize> {
+        time_to_frame(self.to_seconds_at(tempo_map, at), sample_rate)
+    }
+    /// Gives the rate in Hertz of something repeating with this period, like an LFO, at a position in seconds
+    pub fn rate_at(self, tempo_map: &TempoMap, at: f64) -> f64 {
+        self.to_seconds_at(tempo_map, at).recip()
+    }
+}
@@ -0,0 +1,196 @@
+use error::SequencerError;
+use pitch;
+use std::collections::HashMap;
+use std::fmt::Write;
+use {
+    FrequencyId, FrequencyLookupTable, InstrumentId, LoopInfo, Note, Result, Sequence,
+    ValidTimeFrequency,
+};
+
+/// First line of a dump, naming the columns
+const HEADER: &str = "# start end pitch on_velocity off_velocity instrument";
+/// Word starting the lines describing loops
+const LOOP_KEYWORD: &str = "loop";
+/// Suffix of pitches written in Hertz rather than as a note name
+const HERTZ_SUFFIX: &str = "Hz";
+
+/// Writes a Sequence as text, one line per note sorted by time, with the pitch as a note name when it is equal-tempered and in Hertz otherwise.
+/// Loops are written first, one per line, as `loop start end`. Lines starting with `#` are comments.
+/// Detuned notes are written at their detuned frequency.
+/// # Arguments
+/// * sequence: The Sequence to write
+/// * frequency_lut: Table containing the frequencies of the notes of the Sequence
+pub fn dump(sequence: &Sequence, frequency_lut: &FrequencyLookupTable) -> Result<String> {
+    let mut notes: Vec<&Note> = sequence.notes.iter().collect();
+    notes.sort_by(|a, b| a.start_at.total_cmp(&b.start_at));
+    let mut out = String::new();
+    writeln!(out, "{}", HEADER).unwrap();
+    if let Some(ref loops) = sequence.loop_info {
+        for l in loops {
+            writeln!(out, "{} {} {}", LOOP_KEYWORD, l.loop_start, l.loop_end).unwrap();
+        }
+    }
+    for note in notes {
+        let frequency =
+            pitch::shift_by_cents(*frequency_lut.get(&note.frequency_id)?, note.detune);
+        let pitch = match pitch::note_name(frequency) {
+            Some(name) => name,
+            None => format!("{}{}", frequency, HERTZ_SUFFIX),
+        };
+        writeln!(
+            out,
+            "{:<12} {:<12} {:<8} {:<6} {:<6} {}",
+            note.start_at,
+            note.end_at,
+            pitch,
+            note.on_velocity,
+            note.off_velocity,
+            note.instrument_id
+        ).unwrap();
+    }
+    Ok(out)
+}
+
+/// Reads a Sequence written by dump, along with a FrequencyLookupTable for its pitches.
+/// Frequency IDs are given in order of first appearance.
+pub fn parse(text: &str) -> Result<(Sequence, FrequencyLookupTable)> {
+    let mut sequence = Sequence::new();
+    let mut frequencies = Vec::new();
+    let mut frequency_ids: HashMap<u64, FrequencyId> = HashMap::new();
+    for (line_id, line) in text.lines().enumerate() {
+        let line_number = line_id + 1;
+        let line = line.trim();
+        if line.is_empty() | line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields[0] == LOOP_KEYWORD {
+            if fields.len() != 3 {
+                return Err(SequencerError::ParseError(
+                    line_number,
+                    "A loop needs a start and an end".to_string(),
+                ));
+            }
+            sequence
+                .loop_info
+                .get_or_insert_with(Vec::new)
+                .push(LoopInfo {
+                    loop_start: parse_float(fields[1], line_number)?,
+                    loop_end: parse_float(fields[2], line_number)?,
+                });
+            continue;
+        }
+        if fields.len() != 6 {
+            return Err(SequencerError::ParseError(
+                line_number,
+                format!("Expected 6 fields, found {}", fields.len()),
+            ));
+        }
+        let start_at = parse_float(fields[0], line_number)?;
+        let end_at = parse_float(fields[1], line_number)?;
+        let frequency = parse_pitch(fields[2], line_number)?;
+        let instrument_id = InstrumentId(fields[5].parse().map_err(|_| {
+            SequencerError::ParseError(line_number, format!("Invalid instrument: {}", fields[5]))
+        })?);
+        let frequency_id = *frequency_ids
+            .entry(frequency.to_bits())
+            .or_insert_with(|| {
+                frequencies.push(frequency);
+                FrequencyId(frequencies.len() - 1)
+            });
+        sequence.add_note(Note {
+            start_at,
+            end_at,
+            duration: end_at - start_at,
+            frequency_id,
+            on_velocity: parse_float(fields[3], line_number)?,
+            off_velocity: parse_float(fields[4], line_number)?,
+            instrument_id,
+            detune: 0f64,
+        });
+    }
+    Ok((sequence, FrequencyLookupTable::from_frequencies(&frequencies)))
+}
+
+/// Reads a float, giving a ParseError for the line if it is invalid
+fn parse_float(field: &str, line_number: usize) -> Result<f64> {
+    field
+        .parse()
+        .map_err(|_| SequencerError::ParseError(line_number, format!("Invalid number: {}", field)))
+}
+
+/// Reads a pitch written as a note name or in Hertz
+fn parse_pitch(field: &str, line_number: usize) -> Result<f64> {
+    let frequency = match field.strip_suffix(HERTZ_SUFFIX) {
+        Some(hertz) => parse_float(hertz, line_number)?,
+        None => match pitch::parse_note_name(field) {
+            Some(f) => f,
+            None => {
+                return Err(SequencerError::ParseError(
+                    line_number,
+                    format!("Invalid pitch: {}", field),
+                ))
+            }
+        },
+    };
+    if !frequency.is_valid_time_frequency() {
+        return Err(SequencerError::ParseError(
+            line_number,
+            format!("Impossible frequency: {}", field),
+        ));
+    }
+    Ok(frequency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest difference allowed between two frequencies in the tests, in Hertz
+    const EPSILON: f64 = 1e-6;
+
+    #[test]
+    fn dump_then_parse_gives_the_same_notes() {
+        // An equal-tempered pitch written as a note name, and one written in Hertz
+        let frequency_lut = FrequencyLookupTable::from_frequencies(&[440f64, 450f64]);
+        let mut sequence = Sequence::new();
+        sequence.loop_info = Some(vec![LoopInfo {
+            loop_start: 0.5f64,
+            loop_end: 2f64,
+        }]);
+        for &(start_at, end_at, frequency_id, instrument_id) in &[
+            (1f64, 2.5f64, 1, 0),
+            (0f64, 1.25f64, 0, 1),
+            (0.5f64, 0.75f64, 0, 0),
+        ] {
+            sequence.add_note(Note {
+                start_at,
+                end_at,
+                duration: end_at - start_at,
+                frequency_id: FrequencyId(frequency_id),
+                on_velocity: 0.75f64,
+                off_velocity: 0.5f64,
+                instrument_id: InstrumentId(instrument_id),
+                detune: 0f64,
+            });
+        }
+        let text = dump(&sequence, &frequency_lut).unwrap();
+        let (parsed, parsed_lut) = parse(&text).unwrap();
+        let loops = parsed.loop_info.unwrap();
+        assert_eq!(loops.len(), 1);
+        assert_eq!((loops[0].loop_start, loops[0].loop_end), (0.5f64, 2f64));
+        sequence.sort_by_time();
+        assert_eq!(parsed.notes.len(), sequence.notes.len());
+        for (original, read) in sequence.notes.iter().zip(&parsed.notes) {
+            assert_eq!(read.start_at, original.start_at);
+            assert_eq!(read.end_at, original.end_at);
+            assert_eq!(read.duration, original.duration);
+            assert_eq!(read.on_velocity, original.on_velocity);
+            assert_eq!(read.off_velocity, original.off_velocity);
+            assert_eq!(read.instrument_id, original.instrument_id);
+            let original_frequency = frequency_lut.get(&original.frequency_id).unwrap();
+            let read_frequency = parsed_lut.get(&read.frequency_id).unwrap();
+            assert!((read_frequency - original_frequency).abs() < EPSILON);
+        }
+    }
+}
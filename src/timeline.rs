@@ -0,0 +1,234 @@
+use tempo::TempoMap;
+use {InstrumentId, NoteId, Sequence};
+
+/// A change of a controller of an Instrument, like a MIDI control change
+#[derive(Clone)]
+pub struct ControlEvent {
+    /// Time at which the change happens, in seconds
+    pub time: f64,
+    /// Instrument receiving the change
+    pub instrument_id: InstrumentId,
+    /// Which controller changes
+    pub controller: usize,
+    /// New value of the controller
+    pub value: f64,
+}
+
+/// Anything that can happen in a Timeline
+#[derive(Clone)]
+pub enum TimelineEvent {
+    /// The tempo changes to this many beats per minute
+    Tempo(f64),
+    /// A controller changes
+    Control(ControlEvent),
+    /// A note is released, with the index of the note in the Sequence
+    NoteOff(NoteId),
+    /// A note starts, with the index of the note in the Sequence
+    NoteOn(NoteId),
+}
+
+/// An event along with the time at which it happens
+#[derive(Clone)]
+pub struct TimedEvent {
+    /// Time in seconds
+    pub time: f64,
+    /// What happens
+    pub event: TimelineEvent,
+}
+
+/// Decides in which order events happening at the same time are put
+#[derive(Clone, Copy)]
+pub struct TimelineOrder {
+    /// Are notes released before notes start at the same time ? This lets a repeated note stop before starting again.
+    pub offs_before_ons: bool,
+}
+
+impl Default for TimelineOrder {
+    fn default() -> TimelineOrder {
+        TimelineOrder {
+            offs_before_ons: true,
+        }
+    }
+}
+
+impl TimelineOrder {
+    /// Rank of an event among events at the same time, lower comes first
+    fn rank(self, event: &TimelineEvent) -> u8 {
+        match event {
+            TimelineEvent::Tempo(_) => 0,
+            TimelineEvent::Control(_) => 1,
+            TimelineEvent::NoteOff(_) if self.offs_before_ons => 2,
+            TimelineEvent::NoteOn(_) => 3,
+            TimelineEvent::NoteOff(_) => 4,
+        }
+    }
+}
+
+/// Every event of a song sorted by time
+#[derive(Clone, Default)]
+pub struct Timeline {
+    /// The events, sorted
+    pub events: Vec<TimedEvent>,
+}
+
+impl Timeline {
+    /// Merges notes, tempo changes and control changes into a single Timeline.
+    /// At the same time, tempo changes come first, then control changes, then notes with offs before ons unless the order says otherwise.
+    /// Events of the same kind at the same time keep the order they are given in: tempo changes in the order of the TempoMap,
+    /// control changes in the order of the slice and notes in the order of the Sequence.
+    /// Ties are never broken arbitrarily, so the same inputs always give the same Timeline.
+    /// The off of a note always comes after its on: empty notes and notes ending before they start are released right after starting.
+    /// # Arguments
+    /// * sequence: Notes to place
+    /// * tempo_map: Tempo changes to place, their times are converted from beats to seconds
+    /// * controls: Control changes to place
+    /// * order: Order of note offs and note ons at the same time
+    pub fn merge(
+        sequence: &Sequence,
+        tempo_map: &TempoMap,
+        controls: &[ControlEvent],
+        order: TimelineOrder,
+    ) -> Timeline {
+        // Each event is put along with its rank among events at the same time
+        let mut events: Vec<(u8, TimedEvent)> =
+            Vec::with_capacity(tempo_map.changes.len() + controls.len() + sequence.notes.len() * 2);
+        for change in &tempo_map.changes {
+            let event = TimelineEvent::Tempo(change.bpm);
+            events.push((
+                order.rank(&event),
+                TimedEvent {
+                    time: tempo_map.beats_to_seconds(change.at_beat),
+                    event,
+                },
+            ));
+        }
+        for control in controls {
+            let event = TimelineEvent::Control(control.clone());
+            events.push((
+                order.rank(&event),
+                TimedEvent {
+                    time: control.time,
+                    event,
+                },
+            ));
+        }
+        for (note_id, note) in sequence.notes.iter().enumerate() {
+            let on = TimelineEvent::NoteOn(NoteId(note_id));
+            let on_rank = order.rank(&on);
+            let off = TimelineEvent::NoteOff(NoteId(note_id));
+            // A note is never released before it starts, even when it is empty or ends before it starts
+            let (off_time, off_rank) = if note.end_at > note.start_at {
+                (note.end_at, order.rank(&off))
+            } else {
+                (note.start_at, on_rank + 1)
+            };
+            events.push((
+                on_rank,
+                TimedEvent {
+                    time: note.start_at,
+                    event: on,
+                },
+            ));
+            events.push((
+                off_rank,
+                TimedEvent {
+                    time: off_time,
+                    event: off,
+                },
+            ));
+        }
+        // sort_by is stable, events that compare equal keep the order they were pushed in
+        events.sort_by(|a, b| a.1.time.total_cmp(&b.1.time).then(a.0.cmp(&b.0)));
+        let events = events.into_iter().map(|(_, e)| e).collect();
+        Timeline { events }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {FrequencyId, Note};
+
+    /// Gives a short description of every event of a Timeline, to compare it with the expected order
+    fn describe(timeline: &Timeline) -> Vec<String> {
+        timeline
+            .events
+            .iter()
+            .map(|e| match e.event {
+                TimelineEvent::Tempo(bpm) => format!("{} tempo {}", e.time, bpm),
+                TimelineEvent::Control(ref c) => format!("{} control {}", e.time, c.controller),
+                TimelineEvent::NoteOff(id) => format!("{} off {}", e.time, id.0),
+                TimelineEvent::NoteOn(id) => format!("{} on {}", e.time, id.0),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_orders_events_at_the_same_time() {
+        let mut sequence = Sequence::new();
+        // A note ending when the next one starts, an empty note and a note ending before it starts
+        for &(start_at, end_at) in &[(0f64, 1f64), (1f64, 2f64), (1f64, 1f64), (1.5f64, 1.25f64)] {
+            sequence.add_note(Note {
+                start_at,
+                end_at,
+                duration: end_at - start_at,
+                frequency_id: FrequencyId(0),
+                on_velocity: 1f64,
+                off_velocity: 0f64,
+                instrument_id: InstrumentId(0),
+                detune: 0f64,
+            });
+        }
+        // At 60 beats per minute, the change on the second beat happens after one second
+        let mut tempo_map = TempoMap::new(60f64);
+        tempo_map.add_change(1f64, 120f64);
+        let controls = [ControlEvent {
+            time: 1f64,
+            instrument_id: InstrumentId(0),
+            controller: 7,
+            value: 0.5f64,
+        }];
+        let offs_first =
+            Timeline::merge(&sequence, &tempo_map, &controls, TimelineOrder::default());
+        assert_eq!(
+            describe(&offs_first),
+            vec![
+                "0 tempo 60",
+                "0 on 0",
+                "1 tempo 120",
+                "1 control 7",
+                "1 off 0",
+                "1 on 1",
+                "1 on 2",
+                "1 off 2",
+                "1.5 on 3",
+                "1.5 off 3",
+                "2 off 1",
+            ]
+        );
+        let ons_first = Timeline::merge(
+            &sequence,
+            &tempo_map,
+            &controls,
+            TimelineOrder {
+                offs_before_ons: false,
+            },
+        );
+        assert_eq!(
+            describe(&ons_first),
+            vec![
+                "0 tempo 60",
+                "0 on 0",
+                "1 tempo 120",
+                "1 control 7",
+                "1 on 1",
+                "1 on 2",
+                "1 off 0",
+                "1 off 2",
+                "1.5 on 3",
+                "1.5 off 3",
+                "2 off 1",
+            ]
+        );
+    }
+}
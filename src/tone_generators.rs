@@ -8,7 +8,43 @@ pub struct SquareWaveGenerator {}
 /// Generates a Sine Wave
 pub struct SineWaveGenerator {}
 
+/// Generates a pulse wave with its duty cycle following a sequence, like the duty envelopes of NES and Game Boy sound drivers.
+/// Keys are made for each frequency, so every note of an Instrument goes through the same sequence from its start.
+pub struct PulseWaveGenerator {
+    /// Fractions of each period spent high, in order, between 0 and 1. The NES and the Game Boy have 0.125, 0.25, 0.5 and 0.75.
+    /// If empty, the duty cycle stays at 0.5.
+    pub duty_cycles: Vec<f64>,
+    /// How long each duty cycle of the sequence lasts in seconds
+    pub step_duration: f64,
+    /// Goes back to the first duty cycle after the last one, instead of keeping the last one until the end of the key
+    pub loop_sequence: bool,
+}
+
+/// Generates two Sine Waves multiplied together (Ring Modulation)
+pub struct RingModulationGenerator {
+    /// Frequency of the modulator relative to the frequency of the key
+    pub modulator_ratio: f64,
+}
+
+/// Generates noise from a Linear Feedback Shift Register, like the noise channels of the NES or the Game Boy.
+/// The register is shifted once every period of the key frequency.
+pub struct LfsrNoiseGenerator {
+    /// Length of the register in bits, between 2 and 32
+    pub register_length: u32,
+    /// Bit XORed with bit 0 to give the feedback, has to be lower than the register length
+    pub tap: u32,
+}
+
+/// Generates a Sawtooth Wave with its phase reset at the frequency of the key (Hard Sync)
+pub struct HardSyncGenerator {
+    /// Frequency of the slave oscillator relative to the frequency of the key, should be above 1
+    pub slave_ratio: f64,
+}
+
 impl KeyGenerator for SquareWaveGenerator {
+    fn cache_id(&self) -> Option<String> {
+        Some("square".to_string())
+    }
     fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
         match parameters.sample_type {
             Sample::Float(_) => {
@@ -50,16 +86,226 @@ impl KeyGenerator for SquareWaveGenerator {
 }
 
 impl KeyGenerator for SineWaveGenerator {
-    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, _duration: &f64) -> Key {
+    fn cache_id(&self) -> Option<String> {
+        Some("sine".to_string())
+    }
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
+        match parameters.sample_type {
+            Sample::Float(_) => {
+                let sample_rate = f64::from(parameters.sample_rate); // In Hertz
+                let nb_samples = sample_rate * duration; // In number of samples
+                let mut frames = Vec::new();
+                let mut sample = 0f64;
+                while sample < nb_samples {
+                    let pos_seconds = sample / sample_rate;
+                    let mut samples = Vec::new();
+                    for _ in 0..parameters.nb_channels {
+                        samples.push(Sample::Float((pos_seconds * frequency * 2f64 * PI).sin() as f32));
+                    }
+                    frames.push(Frame { samples });
+                    sample += 1f64;
+                }
+                Key {
+                    frequency: *frequency,
+                    audio: PCM {
+                        parameters: parameters.clone(),
+                        loop_info: None,
+                        frames,
+                    },
+                }
+            }
+            _ => unimplemented!("Cannot generate anything but f32 for now"),
+        }
+    }
+}
+
+impl PulseWaveGenerator {
+    /// Pulse wave with a duty cycle that does not change
+    pub fn fixed(duty_cycle: f64) -> PulseWaveGenerator {
+        PulseWaveGenerator {
+            duty_cycles: vec![duty_cycle],
+            step_duration: 1f64,
+            loop_sequence: false,
+        }
+    }
+    /// Gives the duty cycle at a time from the start of the key
+    pub fn duty_cycle_at(&self, time: f64) -> f64 {
+        if self.duty_cycles.is_empty() {
+            return 0.5f64;
+        }
+        let step = if self.step_duration.is_normal() & (self.step_duration > 0f64) {
+            (time / self.step_duration) as usize
+        } else {
+            0
+        };
+        let step = if self.loop_sequence {
+            step % self.duty_cycles.len()
+        } else {
+            step.min(self.duty_cycles.len() - 1)
+        };
+        self.duty_cycles[step].clamp(0f64, 1f64)
+    }
+}
+
+impl KeyGenerator for PulseWaveGenerator {
+    fn cache_id(&self) -> Option<String> {
+        Some(format!(
+            "pulse {:?} {} {}",
+            self.duty_cycles, self.step_duration, self.loop_sequence
+        ))
+    }
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
+        match parameters.sample_type {
+            Sample::Float(_) => {
+                let sample_rate = f64::from(parameters.sample_rate); // In Hertz
+                let nb_samples = sample_rate * duration; // In number of samples
+                let mut frames = Vec::new();
+                let mut sample = 0f64;
+                while sample < nb_samples {
+                    let pos_seconds = sample / sample_rate;
+                    let phase = (pos_seconds * frequency) % 1f64;
+                    let value = if phase < self.duty_cycle_at(pos_seconds) {
+                        1f32
+                    } else {
+                        -1f32
+                    };
+                    let mut samples = Vec::new();
+                    for _ in 0..parameters.nb_channels {
+                        samples.push(Sample::Float(value));
+                    }
+                    frames.push(Frame { samples });
+                    sample += 1f64;
+                }
+                Key {
+                    frequency: *frequency,
+                    audio: PCM {
+                        parameters: parameters.clone(),
+                        loop_info: None,
+                        frames,
+                    },
+                }
+            }
+            _ => unimplemented!("Cannot generate anything but f32 for now"),
+        }
+    }
+}
+
+impl LfsrNoiseGenerator {
+    /// Noise channel of the NES, 15-bit register with the 93-step short mode or the normal 32767-step mode
+    pub fn nes(short_mode: bool) -> LfsrNoiseGenerator {
+        LfsrNoiseGenerator {
+            register_length: 15,
+            tap: if short_mode { 6 } else { 1 },
+        }
+    }
+    /// Noise channel of the Game Boy, 7-bit or 15-bit register
+    pub fn game_boy(short_mode: bool) -> LfsrNoiseGenerator {
+        LfsrNoiseGenerator {
+            register_length: if short_mode { 7 } else { 15 },
+            tap: 1,
+        }
+    }
+}
+
+impl KeyGenerator for LfsrNoiseGenerator {
+    fn cache_id(&self) -> Option<String> {
+        Some(format!("lfsr_noise {} {}", self.register_length, self.tap))
+    }
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
+        match parameters.sample_type {
+            Sample::Float(_) => {
+                let register_length = self.register_length.clamp(2, 32);
+                let tap = self.tap.min(register_length - 1);
+                let sample_rate_period = f64::from(parameters.sample_rate).recip(); // In Seconds
+                let nb_samples = f64::from(parameters.sample_rate) * duration; // In number of samples
+                let step_period = frequency.recip(); // In seconds
+                let mut register = 1u32;
+                let mut frames = Vec::new();
+                let mut pos_sample = 0f64; // In number of samples
+                let mut since_step = 0f64; // In seconds
+                while pos_sample < nb_samples {
+                    while since_step >= step_period {
+                        let feedback = (register & 1) ^ ((register >> tap) & 1);
+                        register = (register >> 1) | (feedback << (register_length - 1));
+                        since_step -= step_period;
+                    }
+                    let value = if register & 1 == 0 { 1f32 } else { -1f32 };
+                    let mut samples = Vec::new();
+                    for _ in 0..parameters.nb_channels {
+                        samples.push(Sample::Float(value));
+                    }
+                    frames.push(Frame { samples });
+                    pos_sample += 1f64;
+                    since_step += sample_rate_period;
+                }
+                Key {
+                    frequency: *frequency,
+                    audio: PCM {
+                        parameters: parameters.clone(),
+                        loop_info: None,
+                        frames,
+                    },
+                }
+            }
+            _ => unimplemented!("Cannot generate anything but f32 for now"),
+        }
+    }
+}
+
+impl KeyGenerator for RingModulationGenerator {
+    fn cache_id(&self) -> Option<String> {
+        Some(format!("ring_modulation {}", self.modulator_ratio))
+    }
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
+        match parameters.sample_type {
+            Sample::Float(_) => {
+                let sample_rate = f64::from(parameters.sample_rate); // In Hertz
+                let nb_samples = sample_rate * duration; // In number of samples
+                let modulator_frequency = frequency * self.modulator_ratio; // In Hertz
+                let mut frames = Vec::new();
+                let mut sample = 0f64;
+                while sample < nb_samples {
+                    let pos_seconds = sample / sample_rate;
+                    let value = (pos_seconds * frequency * 2f64 * PI).sin()
+                        * (pos_seconds * modulator_frequency * 2f64 * PI).sin();
+                    let mut samples = Vec::new();
+                    for _ in 0..parameters.nb_channels {
+                        samples.push(Sample::Float(value as f32));
+                    }
+                    frames.push(Frame { samples });
+                    sample += 1f64;
+                }
+                Key {
+                    frequency: *frequency,
+                    audio: PCM {
+                        parameters: parameters.clone(),
+                        loop_info: None,
+                        frames,
+                    },
+                }
+            }
+            _ => unimplemented!("Cannot generate anything but f32 for now"),
+        }
+    }
+}
+
+impl KeyGenerator for HardSyncGenerator {
+    fn cache_id(&self) -> Option<String> {
+        Some(format!("hard_sync {}", self.slave_ratio))
+    }
+    fn key_gen(&self, frequency: &f64, parameters: &PCMParameters, duration: &f64) -> Key {
         match parameters.sample_type {
             Sample::Float(_) => {
-                let nb_samples = f64::from(parameters.sample_rate) / frequency;
+                let sample_rate = f64::from(parameters.sample_rate); // In Hertz
+                let nb_samples = sample_rate * duration; // In number of samples
                 let mut frames = Vec::new();
                 let mut sample = 0f64;
-                while sample <= nb_samples {
+                while sample < nb_samples {
+                    let master_phase = (sample / sample_rate * frequency) % 1f64;
+                    let slave_phase = (master_phase * self.slave_ratio) % 1f64;
                     let mut samples = Vec::new();
                     for _ in 0..parameters.nb_channels {
-                        samples.push(Sample::Float(((sample / nb_samples) * 2f64 * PI).sin() as f32));
+                        samples.push(Sample::Float((slave_phase * 2f64 - 1f64) as f32));
                     }
                     frames.push(Frame { samples });
                     sample += 1f64;
@@ -77,3 +323,71 @@ impl KeyGenerator for SineWaveGenerator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample rates every generator has to give the same pitch at
+    const SAMPLE_RATES: [u32; 4] = [22_050, 44_100, 48_000, 96_000];
+    /// Frequency of the generated keys, in Hertz
+    const FREQUENCY: f64 = 440f64;
+    /// Length of the generated keys, in seconds
+    const DURATION: f64 = 0.1f64;
+
+    /// Generates a mono key and gives the average time between its rising zero crossings, in seconds.
+    /// Crossings are placed between samples by linear interpolation.
+    fn zero_crossing_period(generator: &dyn KeyGenerator, sample_rate: u32) -> f64 {
+        let parameters = PCMParameters {
+            sample_rate,
+            nb_channels: 1,
+            sample_type: Sample::Float(0f32),
+        };
+        let key = generator.key_gen(&FREQUENCY, &parameters, &DURATION);
+        let values: Vec<f64> = key
+            .audio
+            .frames
+            .iter()
+            .map(|f| match f.samples[0] {
+                Sample::Float(s) => f64::from(s),
+                _ => panic!("Generators should give Float samples"),
+            })
+            .collect();
+        let crossings: Vec<f64> = values
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| (w[0] < 0f64) & (w[1] >= 0f64))
+            .map(|(frame_id, w)| (frame_id as f64 + w[0] / (w[0] - w[1])) / f64::from(sample_rate))
+            .collect();
+        assert!(crossings.len() > 2, "Not enough zero crossings at {}Hz", sample_rate);
+        (crossings[crossings.len() - 1] - crossings[0]) / (crossings.len() - 1) as f64
+    }
+
+    /// Checks that the zero crossings of a generator are one period of FREQUENCY apart at every sample rate, within one sample
+    fn assert_period_at_every_rate(generator: &dyn KeyGenerator) {
+        for &sample_rate in &SAMPLE_RATES {
+            let period = zero_crossing_period(generator, sample_rate);
+            assert!(
+                (period - FREQUENCY.recip()).abs() < f64::from(sample_rate).recip(),
+                "Period of {}s at {}Hz",
+                period,
+                sample_rate
+            );
+        }
+    }
+
+    #[test]
+    fn sine_period_across_sample_rates() {
+        assert_period_at_every_rate(&SineWaveGenerator {});
+    }
+
+    #[test]
+    fn square_period_across_sample_rates() {
+        assert_period_at_every_rate(&SquareWaveGenerator {});
+    }
+
+    #[test]
+    fn pulse_period_across_sample_rates() {
+        assert_period_at_every_rate(&PulseWaveGenerator::fixed(0.25f64));
+    }
+}
@@ -0,0 +1,292 @@
+use adapters::{buffer_to_pcm, AudioBuffer};
+use error::SequencerError;
+use pcm::PCM;
+use std::f64::consts::FRAC_PI_2;
+use {duration_to_frames, time_to_frame, MusicSequencer, Result};
+
+/// Shape of the gain of a fade over time
+#[derive(Clone, Copy)]
+pub enum FadeCurve {
+    /// Gain changes linearly, the level dips in the middle of a crossfade between different sounds
+    Linear,
+    /// Gains follow a quarter of sine and cosine, the power stays constant during a crossfade between different sounds
+    EqualPower,
+}
+
+impl FadeCurve {
+    /// Gives the gain of a fade in at a position between 0 (start, silent) and 1 (end, full level).
+    /// The gain of a fade out is the gain of a fade in at 1 minus the position.
+    pub fn fade_in_gain(self, position: f64) -> f64 {
+        let position = position.clamp(0f64, 1f64);
+        match self {
+            FadeCurve::Linear => position,
+            FadeCurve::EqualPower => (position * FRAC_PI_2).sin(),
+        }
+    }
+}
+
+/// How to go from one song to another
+#[derive(Clone)]
+pub struct Crossfade {
+    /// Length of the part where both songs play, in seconds
+    pub overlap: f64,
+    /// Shape of the fades
+    pub curve: FadeCurve,
+    /// Tempo of the second song divided by the tempo of the first one, to ramp the speed of both songs during the overlap
+    /// so that the first one ends at the speed of the second one and the second one starts at the speed of the first one.
+    /// Like on a turntable, the pitch follows the speed. If not set, both play at their normal speed.
+    pub tempo_ratio: Option<f64>,
+}
+
+/// Renders two sequencers and crossfades the end of the first one into the start of the second one.
+/// Both need the same sample rate and amount of channels.
+pub fn crossfade(
+    from: &mut MusicSequencer,
+    to: &mut MusicSequencer,
+    settings: &Crossfade,
+) -> Result<PCM> {
+    if (from.pcm_parameters.sample_rate != to.pcm_parameters.sample_rate)
+        | (from.pcm_parameters.nb_channels != to.pcm_parameters.nb_channels)
+    {
+        return Err(SequencerError::MismatchedParameters);
+    }
+    let from = from.render_buffer()?;
+    let to = to.render_buffer()?;
+    Ok(buffer_to_pcm(&crossfade_buffers(from, &to, settings)?))
+}
+
+/// Crossfades the end of a buffer into the start of another one with the same sample rate and amount of channels
+fn crossfade_buffers(
+    from: AudioBuffer,
+    to: &AudioBuffer,
+    settings: &Crossfade,
+) -> Result<AudioBuffer> {
+    let nb_channels = usize::from(from.nb_channels);
+    let ratio = settings.tempo_ratio.unwrap_or(1f64);
+    if !(ratio.is_finite() & (ratio > 0f64)) {
+        return Err(SequencerError::ImpossibleTimeOrFrequency(ratio));
+    }
+    // Average speed over the overlap is the middle of the ramp, which tells how much of each song the overlap uses
+    let nb_overlap = duration_to_frames(settings.overlap, from.sample_rate)?
+        .min((from.nb_frames() as f64 * 2f64 / (1f64 + ratio)) as usize)
+        .min((to.nb_frames() as f64 * 2f64 / (1f64 + ratio.recip())) as usize);
+    let from_used =
+        ((nb_overlap as f64 * (1f64 + ratio) / 2f64).round() as usize).min(from.nb_frames());
+    let to_used =
+        ((nb_overlap as f64 * (1f64 + ratio.recip()) / 2f64).round() as usize).min(to.nb_frames());
+    let from_tail_start = from.nb_frames() - from_used;
+    let from_tail = warp(
+        &from.samples[from_tail_start * nb_channels..],
+        nb_channels,
+        1f64,
+        ratio,
+        nb_overlap,
+    );
+    let to_head = warp(&to.samples, nb_channels, ratio.recip(), 1f64, nb_overlap);
+    let mut samples = from.samples;
+    samples.truncate(from_tail_start * nb_channels);
+    samples.reserve((nb_overlap + to.nb_frames() - to_used) * nb_channels);
+    for frame_id in 0..nb_overlap {
+        let position = (frame_id as f64 + 0.5f64) / nb_overlap as f64;
+        let fade_out = settings.curve.fade_in_gain(1f64 - position) as f32;
+        let fade_in = settings.curve.fade_in_gain(position) as f32;
+        for channel in 0..nb_channels {
+            let id = frame_id * nb_channels + channel;
+            samples.push(from_tail[id] * fade_out + to_head[id] * fade_in);
+        }
+    }
+    samples.extend_from_slice(&to.samples[to_used * nb_channels..]);
+    Ok(AudioBuffer {
+        sample_rate: from.sample_rate,
+        nb_channels: from.nb_channels,
+        samples,
+    })
+}
+
+/// How a song of an album starts after the previous one
+#[derive(Clone)]
+pub enum TrackTransition {
+    /// Silence between the songs, in seconds. 0 plays them back-to-back without any gap.
+    Gap(f64),
+    /// The end of the previous song is crossfaded into the start of this one
+    Crossfade(Crossfade),
+}
+
+/// Fade to silence at the end of a song
+#[derive(Clone)]
+pub struct FadeOut {
+    /// Length of the fade in seconds
+    pub duration: f64,
+    /// Shape of the fade
+    pub curve: FadeCurve,
+}
+
+/// A song of an album
+pub struct AlbumTrack<'a> {
+    /// Sequencer playing the song
+    pub sequencer: &'a mut MusicSequencer,
+    /// How many times the first loop of the Sequence is played before the song ends, the fade out continues looping.
+    /// If None or if the Sequence has no loop, the song plays once to its end.
+    pub loop_count: Option<u32>,
+    /// Fade at the end of the song. If not set, the song stops at the end of its last loop or of its sound.
+    pub fade_out: Option<FadeOut>,
+    /// How the song starts after the previous one, not used for the first song
+    pub transition: TrackTransition,
+}
+
+impl<'a> AlbumTrack<'a> {
+    /// Renders the song with its loop repeated and its fade out
+    fn render(&mut self) -> Result<AudioBuffer> {
+        let song = self.sequencer.render_buffer()?;
+        let fade_duration = self.fade_out.as_ref().map_or(0f64, |f| f.duration);
+        let first_loop = self.sequencer
+            .sequence
+            .loop_info
+            .as_ref()
+            .and_then(|l| l.first());
+        let mut song = match (self.loop_count, first_loop) {
+            (Some(count), Some(l)) => {
+                let loop_start = time_to_frame(l.loop_start, song.sample_rate)?;
+                let loop_end = time_to_frame(l.loop_end, song.sample_rate)?;
+                if loop_end <= loop_start {
+                    return Err(SequencerError::ImpossibleTimeOrFrequency(
+                        l.loop_end - l.loop_start,
+                    ));
+                }
+                let fade_frames = duration_to_frames(fade_duration, song.sample_rate)?;
+                let nb_frames = (loop_end - loop_start)
+                    .checked_mul(count as usize)
+                    .and_then(|n| n.checked_add(loop_start + fade_frames))
+                    .ok_or(SequencerError::TimeOutOfRange(
+                        l.loop_start + (l.loop_end - l.loop_start) * f64::from(count) + fade_duration,
+                    ))?;
+                repeat_loop(&song, loop_start, loop_end, nb_frames)
+            }
+            _ => song,
+        };
+        if let Some(ref fade) = self.fade_out {
+            fade_out(&mut song, fade)?;
+        }
+        Ok(song)
+    }
+}
+
+/// Renders songs one after the other into a single PCM, for exporting a whole soundtrack as one file.
+/// Every sequencer needs the same sample rate and amount of channels.
+pub fn render_album(tracks: &mut [AlbumTrack]) -> Result<PCM> {
+    let mut album: Option<AudioBuffer> = None;
+    for track in tracks.iter_mut() {
+        let song = track.render()?;
+        album = Some(match album {
+            None => song,
+            Some(mut previous) => {
+                if (previous.sample_rate != song.sample_rate)
+                    | (previous.nb_channels != song.nb_channels)
+                {
+                    return Err(SequencerError::MismatchedParameters);
+                }
+                match track.transition {
+                    TrackTransition::Gap(gap) => {
+                        let nb_gap_frames = duration_to_frames(gap, song.sample_rate)?;
+                        let nb_samples = previous.samples.len()
+                            + (nb_gap_frames * usize::from(song.nb_channels));
+                        previous.samples.resize(nb_samples, 0f32);
+                        previous.samples.extend_from_slice(&song.samples);
+                        previous
+                    }
+                    TrackTransition::Crossfade(ref c) => crossfade_buffers(previous, &song, c)?,
+                }
+            }
+        });
+    }
+    match album {
+        Some(a) => Ok(buffer_to_pcm(&a)),
+        None => Err(SequencerError::EmptyAlbum),
+    }
+}
+
+/// Plays a song up to the end of its loop then repeats the loop, up to an amount of frames.
+/// The sound going on past the end of the loop, like releases, is cut.
+fn repeat_loop(
+    song: &AudioBuffer,
+    loop_start: usize,
+    loop_end: usize,
+    nb_frames: usize,
+) -> AudioBuffer {
+    let nb_channels = usize::from(song.nb_channels);
+    let mut samples = Vec::with_capacity(nb_frames * nb_channels);
+    for frame_id in 0..nb_frames {
+        let source = if frame_id < loop_end {
+            frame_id
+        } else {
+            loop_start + (frame_id - loop_end) % (loop_end - loop_start)
+        };
+        match song
+            .samples
+            .get(source * nb_channels..(source + 1) * nb_channels)
+        {
+            Some(f) => samples.extend_from_slice(f),
+            None => samples.extend((0..nb_channels).map(|_| 0f32)),
+        }
+    }
+    AudioBuffer {
+        sample_rate: song.sample_rate,
+        nb_channels: song.nb_channels,
+        samples,
+    }
+}
+
+/// Fades the end of a song to silence
+fn fade_out(song: &mut AudioBuffer, fade: &FadeOut) -> Result<()> {
+    let nb_channels = usize::from(song.nb_channels.max(1));
+    let nb_fade = duration_to_frames(fade.duration, song.sample_rate)?.min(song.nb_frames());
+    let fade_start = song.nb_frames() - nb_fade;
+    for (frame_id, frame) in song.samples[fade_start * nb_channels..]
+        .chunks_mut(nb_channels)
+        .enumerate()
+    {
+        let position = (frame_id as f64 + 0.5f64) / nb_fade as f64;
+        let gain = fade.curve.fade_in_gain(1f64 - position) as f32;
+        for sample in frame {
+            *sample *= gain;
+        }
+    }
+    Ok(())
+}
+
+/// Reads interleaved samples with a speed ramping linearly, interpolating linearly between frames.
+/// Frames past the end are silent.
+/// # Arguments
+/// * samples: Interleaved samples to read from their start
+/// * nb_channels: Amount of channels of the samples
+/// * start_speed: Frames read per output frame at the start
+/// * end_speed: Frames read per output frame at the end
+/// * nb_frames: Amount of frames to output
+fn warp(
+    samples: &[f32],
+    nb_channels: usize,
+    start_speed: f64,
+    end_speed: f64,
+    nb_frames: usize,
+) -> Vec<f32> {
+    let nb_in = samples.len() / nb_channels.max(1);
+    let mut out = Vec::with_capacity(nb_frames * nb_channels);
+    let mut position = 0f64;
+    for frame_id in 0..nb_frames {
+        let index = position as usize;
+        let fraction = (position - index as f64) as f32;
+        for channel in 0..nb_channels {
+            let get = |i: usize| {
+                if i < nb_in {
+                    samples[i * nb_channels + channel]
+                } else {
+                    0f32
+                }
+            };
+            out.push(get(index) * (1f32 - fraction) + get(index + 1) * fraction);
+        }
+        position +=
+            start_speed + (end_speed - start_speed) * (frame_id as f64 + 0.5f64) / nb_frames as f64;
+    }
+    out
+}
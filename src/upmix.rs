@@ -0,0 +1,69 @@
+use pcm::{Frame, Sample};
+
+/// Index of the front center channel in the WAV channel order (front left, front right, front center, LFE, surrounds)
+const CENTER_CHANNEL: usize = 2;
+
+/// How the sound of an Instrument is spread when it has fewer channels than the project, like stereo samples in a 5.1 project.
+/// Mono sounds always go to both front channels. Sounds with more channels than the project lose their last channels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UpmixPolicy {
+    /// Output channels past the ones of the sound repeat its channels in order, so surrounds get a copy of the fronts
+    #[default]
+    Duplicate,
+    /// The front channels keep the sound, the front center gets the average of its front channels and the other channels are silent
+    CenterExtract,
+    /// The front channels keep the sound and the other channels are silent
+    SilenceSurrounds,
+}
+
+impl UpmixPolicy {
+    /// Spreads a frame over an amount of channels. Only Float samples are averaged for the front center.
+    pub fn upmix_frame(self, frame: &Frame, nb_channels: usize) -> Frame {
+        let input = &frame.samples;
+        if input.is_empty() {
+            return Frame {
+                samples: vec![Sample::Float(0f32); nb_channels],
+            };
+        }
+        let nb_fronts = input.len().min(2);
+        let mut samples = Vec::with_capacity(nb_channels);
+        for channel in 0..nb_channels {
+            samples.push(if channel < input.len() {
+                input[channel].clone()
+            } else if channel < 2 {
+                input[channel % nb_fronts].clone()
+            } else {
+                match self {
+                    UpmixPolicy::Duplicate => input[channel % input.len()].clone(),
+                    UpmixPolicy::CenterExtract if channel == CENTER_CHANNEL => {
+                        let mut sum = 0f32;
+                        for sample in &input[..nb_fronts] {
+                            if let Sample::Float(s) = sample {
+                                sum += s;
+                            }
+                        }
+                        Sample::Float(sum / nb_fronts as f32)
+                    }
+                    _ => Sample::Float(0f32),
+                }
+            });
+        }
+        Frame { samples }
+    }
+    /// Spreads frames over an amount of channels, frames that already have this amount are left untouched
+    pub fn upmix_frames(self, frames: Vec<Frame>, nb_channels: usize) -> Vec<Frame> {
+        if frames.iter().all(|f| f.samples.len() == nb_channels) {
+            return frames;
+        }
+        frames
+            .iter()
+            .map(|f| {
+                if f.samples.len() == nb_channels {
+                    f.clone()
+                } else {
+                    self.upmix_frame(f, nb_channels)
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,49 @@
+/// Shape of a velocity mapping, takes a velocity between 0 and 1 and gives a position between 0 and 1
+#[derive(Clone)]
+pub enum VelocityCurve {
+    /// The velocity is used as is
+    Linear,
+    /// The velocity is raised to a power, above 1 makes soft notes softer and below 1 makes them louder
+    Power(f64),
+    /// Points evenly spaced from velocity 0 to velocity 1, linearly interpolated in between
+    Table(Vec<f64>),
+}
+
+/// Maps the velocity of a note to the value of a parameter
+#[derive(Clone)]
+pub struct VelocityMapping {
+    /// Shape of the mapping
+    pub curve: VelocityCurve,
+    /// Value of the parameter at velocity 0
+    pub min: f64,
+    /// Value of the parameter at velocity 1
+    pub max: f64,
+}
+
+impl VelocityCurve {
+    /// Gives the position on the curve for a velocity, the velocity is clamped between 0 and 1
+    pub fn apply(&self, velocity: f64) -> f64 {
+        let velocity = velocity.clamp(0f64, 1f64);
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Power(p) => velocity.powf(*p),
+            VelocityCurve::Table(points) => match points.len() {
+                0 => velocity,
+                1 => points[0],
+                len => {
+                    let position = velocity * (len - 1) as f64;
+                    let index = (position as usize).min(len - 2);
+                    let fraction = position - index as f64;
+                    points[index] + (points[index + 1] - points[index]) * fraction
+                }
+            },
+        }
+    }
+}
+
+impl VelocityMapping {
+    /// Gives the value of the parameter for a velocity
+    pub fn map(&self, velocity: f64) -> f64 {
+        self.min + (self.max - self.min) * self.curve.apply(velocity)
+    }
+}